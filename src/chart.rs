@@ -0,0 +1,127 @@
+use std::{ops::RangeInclusive, path::Path};
+
+use bms_rs::bms::{
+    BmsOutput,
+    ast::rng::{RandRng, Rng},
+    model::Bms,
+    parse_bms_with_rng,
+    prelude::KeyLayoutBeat,
+};
+use bms_rs::command::JudgeLevel;
+use encoding_rs::SHIFT_JIS;
+use num_bigint::BigUint;
+use num_traits::ToPrimitive;
+use rand::{SeedableRng, rngs::StdRng};
+use sha2::{Digest, Sha256};
+
+pub struct Chart {
+    pub bms: Bms,
+    /// Values drawn for each `#RANDOM`/`#SWITCH` block, in source order.
+    pub random_values: Vec<u64>,
+    pub judge_rank: JudgeRank,
+}
+
+/// The judge difficulty a chart declares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JudgeRank {
+    /// `#RANK`, NORMAL when the chart declares nothing.
+    Rank(JudgeLevel),
+    /// `#DEFEXRANK`, a percentage where 100 is NORMAL. Takes precedence over `#RANK`.
+    DefExRank(u64),
+}
+
+impl JudgeRank {
+    pub fn new(bms: &Bms, source: &str) -> Self {
+        match def_ex_rank(source) {
+            Some(percent) => JudgeRank::DefExRank(percent),
+            None => JudgeRank::Rank(bms.header.rank.unwrap_or(JudgeLevel::Normal)),
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            JudgeRank::Rank(JudgeLevel::VeryHard) => "VERY HARD".to_string(),
+            JudgeRank::Rank(JudgeLevel::Hard) => "HARD".to_string(),
+            JudgeRank::Rank(JudgeLevel::Normal) => "NORMAL".to_string(),
+            JudgeRank::Rank(JudgeLevel::Easy) => "EASY".to_string(),
+            JudgeRank::Rank(JudgeLevel::OtherInt(rank)) => format!("RANK {}", rank),
+            JudgeRank::DefExRank(percent) => format!("EXRANK {}%", percent),
+        }
+    }
+}
+
+/// bms-rs lexes `#DEFEXRANK` but drops it while parsing, so it is read from the source.
+fn def_ex_rank(source: &str) -> Option<u64> {
+    source.lines().find_map(|line| {
+        let line = line.trim();
+        let command = line.get(..10)?;
+        if !command.eq_ignore_ascii_case("#DEFEXRANK") {
+            return None;
+        }
+        line[10..].trim().parse().ok()
+    })
+}
+
+struct RecordingRng {
+    rng: RandRng<StdRng>,
+    values: Vec<u64>,
+}
+
+impl Rng for &mut RecordingRng {
+    fn generate(&mut self, range: RangeInclusive<BigUint>) -> BigUint {
+        let value = self.rng.generate(range);
+        self.values.push(value.to_u64().unwrap_or(u64::MAX));
+        value
+    }
+}
+
+/// SHA-256 of the chart file, identifying a chart regardless of where it is stored.
+pub fn chart_hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Parses the chart at `path`, evaluating its control flow with an RNG seeded by `seed`,
+/// so the same seed always picks the same branches.
+pub fn parse_chart(path: &Path, seed: u64) -> std::io::Result<Chart> {
+    let bytes = std::fs::read(path)?;
+    let (source, _encoding_used, _had_errors) = SHIFT_JIS.decode(&bytes);
+
+    let mut rng = RecordingRng {
+        rng: RandRng(StdRng::seed_from_u64(seed)),
+        values: vec![],
+    };
+    let BmsOutput { bms, .. }: BmsOutput<KeyLayoutBeat> = parse_bms_with_rng(&source, &mut rng);
+
+    Ok(Chart {
+        judge_rank: JudgeRank::new(&bms, &source),
+        bms,
+        random_values: rng.values,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_def_ex_rank() {
+        assert_eq!(def_ex_rank("#TITLE test\n#DEFEXRANK 120\n"), Some(120));
+        assert_eq!(def_ex_rank("  #defexrank  75  \r\n"), Some(75));
+        assert_eq!(def_ex_rank("#RANK 2\n"), None);
+        assert_eq!(def_ex_rank("#DEFEXRANK\n"), None);
+        assert_eq!(def_ex_rank("#DEFEXRANK abc\n"), None);
+    }
+
+    #[test]
+    fn def_ex_rank_takes_precedence() {
+        let bms = Bms::default();
+        assert_eq!(
+            JudgeRank::new(&bms, "#TITLE test\n"),
+            JudgeRank::Rank(JudgeLevel::Normal)
+        );
+        assert_eq!(
+            JudgeRank::new(&bms, "#RANK 0\n#DEFEXRANK 120\n"),
+            JudgeRank::DefExRank(120)
+        );
+    }
+}
@@ -4,8 +4,12 @@ use bevy::{
     window::{PresentMode, WindowResolution},
 };
 
+mod chart;
+mod replay;
 mod resources;
+mod score_store;
 mod screens;
+mod timing_offsets;
 
 fn main() {
     App::new()
@@ -0,0 +1,73 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::resources::GaugeType;
+
+const REPLAY_DIR: &str = "./replays";
+
+/// A key press or release, `time` in song seconds.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ReplayEvent {
+    pub time: f32,
+    /// Index of the lane in gameplay's lane order.
+    pub lane: u8,
+    pub pressed: bool,
+}
+
+/// Everything needed to re-simulate a play.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replay {
+    pub chart_hash: String,
+    pub random_seed: u64,
+    pub gauge: GaugeType,
+    pub green_number: u32,
+    pub events: Vec<ReplayEvent>,
+}
+
+impl Replay {
+    /// Saves to `./replays/<chart hash>_<unix time>.ron`.
+    pub fn save(&self) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let path = Path::new(REPLAY_DIR).join(format!("{}_{}.ron", self.chart_hash, timestamp));
+        let result = fs::create_dir_all(REPLAY_DIR)
+            .and_then(|_| fs::write(&path, ron::to_string(self).unwrap()));
+        if let Err(err) = result {
+            warn!("failed to write {}: {}", path.display(), err);
+        }
+    }
+
+    /// The most recent replay of the chart with `chart_hash`.
+    pub fn load_latest(chart_hash: &str) -> Option<Self> {
+        let prefix = format!("{}_", chart_hash);
+        let latest: PathBuf = fs::read_dir(REPLAY_DIR)
+            .ok()?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with(&prefix) && name.ends_with(".ron"))
+            })
+            .max()?;
+
+        let text = fs::read_to_string(&latest).ok()?;
+        ron::from_str(&text)
+            .inspect_err(|err| warn!("failed to read {}: {}", latest.display(), err))
+            .ok()
+    }
+}
+
+/// Present while gameplay is replaying instead of taking input. The play takes its `#RANDOM`
+/// seed and gauge from the replay, leaving `PlayOptions` as the player set them.
+#[derive(Resource)]
+pub struct ReplayPlayback {
+    pub replay: Replay,
+    /// Index of the next event to feed.
+    pub next: usize,
+}
@@ -2,10 +2,15 @@ use std::path::{Path, PathBuf};
 
 use bevy::prelude::*;
 use bms_rs::bms::model::Header;
+use serde::{Deserialize, Serialize};
+
+use crate::chart::JudgeRank;
 
 pub struct BmsEntry {
     pub header: Header,
+    pub judge_rank: JudgeRank,
     pub path: PathBuf,
+    pub hash: String,
 }
 
 #[derive(Resource)]
@@ -27,3 +32,256 @@ impl BmsLib {
             .and_then(|entry| entry.path.parent())
     }
 }
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PlaySide {
+    #[default]
+    P1,
+    P2,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GaugeType {
+    AssistEasy,
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+    ExHard,
+    Hazard,
+}
+
+impl GaugeType {
+    pub fn all() -> &'static [GaugeType] {
+        &[
+            GaugeType::AssistEasy,
+            GaugeType::Easy,
+            GaugeType::Normal,
+            GaugeType::Hard,
+            GaugeType::ExHard,
+            GaugeType::Hazard,
+        ]
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            GaugeType::AssistEasy => "ASSIST EASY",
+            GaugeType::Easy => "EASY",
+            GaugeType::Normal => "NORMAL",
+            GaugeType::Hard => "HARD",
+            GaugeType::ExHard => "EX-HARD",
+            GaugeType::Hazard => "HAZARD",
+        }
+    }
+
+    /// Survival gauges start full and fail the play when emptied.
+    pub fn is_survival(self) -> bool {
+        matches!(
+            self,
+            GaugeType::Hard | GaugeType::ExHard | GaugeType::Hazard
+        )
+    }
+
+    /// The gauge needed at the end of the chart to clear, survival gauges only need to survive.
+    pub fn clear_line(self) -> f32 {
+        match self {
+            GaugeType::AssistEasy => 60.,
+            GaugeType::Easy | GaugeType::Normal => 80.,
+            GaugeType::Hard | GaugeType::ExHard | GaugeType::Hazard => 0.,
+        }
+    }
+
+    pub fn color(self) -> Color {
+        match self {
+            GaugeType::AssistEasy => Color::srgb(0.6, 0.3, 1.),
+            GaugeType::Easy => Color::srgb(0.3, 1., 0.3),
+            GaugeType::Normal => Color::srgb(0.3, 0.8, 1.),
+            GaugeType::Hard => Color::srgb(1., 0.2, 0.2),
+            GaugeType::ExHard => Color::srgb(1., 1., 0.2),
+            GaugeType::Hazard => Color::srgb(1., 0.5, 0.),
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct PlayOptions {
+    pub side: PlaySide,
+    pub gauge: GaugeType,
+    /// Draw markers in the lanes where the BPM changes or the chart stops.
+    pub timing_markers: bool,
+    /// Hit every note on time instead of taking input.
+    pub autoplay: bool,
+    /// Show the offset in milliseconds next to FAST/SLOW.
+    pub offset_ms: bool,
+    pub pacemaker: PacemakerTarget,
+    /// Seed for `#RANDOM` evaluation. Kept until the cursor moves or it is re-rolled,
+    /// so playing the same chart again gives the same variant.
+    pub random_seed: u64,
+}
+
+/// What the pacemaker next to the lanes compares the EX score against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacemakerTarget {
+    Grade(DjLevel),
+    PersonalBest,
+    /// The latest replay of the chart, at the same point in the chart.
+    Replay,
+}
+
+impl Default for PacemakerTarget {
+    fn default() -> Self {
+        PacemakerTarget::Grade(DjLevel::Aaa)
+    }
+}
+
+impl PacemakerTarget {
+    pub fn all() -> &'static [PacemakerTarget] {
+        &[
+            PacemakerTarget::Grade(DjLevel::A),
+            PacemakerTarget::Grade(DjLevel::Aa),
+            PacemakerTarget::Grade(DjLevel::Aaa),
+            PacemakerTarget::PersonalBest,
+            PacemakerTarget::Replay,
+        ]
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            PacemakerTarget::Grade(level) => level.label(),
+            PacemakerTarget::PersonalBest => "BEST",
+            PacemakerTarget::Replay => "REPLAY",
+        }
+    }
+}
+
+/// Present while select idles into an autoplay demo of the cursor chart.
+#[derive(Resource)]
+pub struct DemoPlay;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DjLevel {
+    F,
+    E,
+    D,
+    C,
+    B,
+    A,
+    Aa,
+    Aaa,
+}
+
+impl DjLevel {
+    /// Grade by EX score rate in ninths of the max, AAA from 8/9.
+    pub fn new(ex_score: u32, max_ex_score: u32) -> Self {
+        if max_ex_score == 0 {
+            return DjLevel::F;
+        }
+        match ex_score * 9 / max_ex_score {
+            8.. => DjLevel::Aaa,
+            7 => DjLevel::Aa,
+            6 => DjLevel::A,
+            5 => DjLevel::B,
+            4 => DjLevel::C,
+            3 => DjLevel::D,
+            2 => DjLevel::E,
+            _ => DjLevel::F,
+        }
+    }
+
+    /// The lowest EX score rate reaching this grade.
+    pub fn min_rate(self) -> f32 {
+        match self {
+            DjLevel::F => 0.,
+            DjLevel::E => 2. / 9.,
+            DjLevel::D => 3. / 9.,
+            DjLevel::C => 4. / 9.,
+            DjLevel::B => 5. / 9.,
+            DjLevel::A => 6. / 9.,
+            DjLevel::Aa => 7. / 9.,
+            DjLevel::Aaa => 8. / 9.,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            DjLevel::F => "F",
+            DjLevel::E => "E",
+            DjLevel::D => "D",
+            DjLevel::C => "C",
+            DjLevel::B => "B",
+            DjLevel::A => "A",
+            DjLevel::Aa => "AA",
+            DjLevel::Aaa => "AAA",
+        }
+    }
+}
+
+/// 2 for each PGREAT and 1 for each GREAT.
+pub fn ex_score(pgreat: u32, great: u32) -> u32 {
+    pgreat * 2 + great
+}
+
+/// The EX score with every note PGREAT.
+pub fn max_ex_score(notes: usize) -> u32 {
+    notes as u32 * 2
+}
+
+/// Outcome of the last play, left behind by gameplay for the result screen.
+#[derive(Resource, Debug, Clone)]
+pub struct PlayResult {
+    pub gauge: GaugeType,
+    pub cleared: bool,
+    pub notes: usize,
+    pub pgreat: u32,
+    pub great: u32,
+    pub good: u32,
+    pub bad: u32,
+    pub poor: u32,
+    pub empty_poor: u32,
+    pub max_combo: u32,
+    /// GREAT/GOOD/BAD pressed before the note.
+    pub fast: u32,
+    /// GREAT/GOOD/BAD pressed after the note.
+    pub slow: u32,
+    /// Signed offset in milliseconds of every hit note, negative when early.
+    pub offsets: Vec<f32>,
+    /// Gauge value after each judgement.
+    pub gauge_history: Vec<f32>,
+    /// Watched from a replay or autoplay, so it doesn't update the records.
+    pub watched: bool,
+}
+
+impl PlayResult {
+    pub fn ex_score(&self) -> u32 {
+        ex_score(self.pgreat, self.great)
+    }
+
+    pub fn max_ex_score(&self) -> u32 {
+        max_ex_score(self.notes)
+    }
+
+    pub fn dj_level(&self) -> DjLevel {
+        DjLevel::new(self.ex_score(), self.max_ex_score())
+    }
+
+    /// Mean and standard deviation of the offsets in milliseconds.
+    pub fn offset_stats(&self) -> Option<(f32, f32)> {
+        if self.offsets.is_empty() {
+            return None;
+        }
+        let count = self.offsets.len() as f32;
+        let mean = self.offsets.iter().sum::<f32>() / count;
+        let variance = self
+            .offsets
+            .iter()
+            .map(|offset| (offset - mean).powi(2))
+            .sum::<f32>()
+            / count;
+        Some((mean, variance.sqrt()))
+    }
+
+    /// BAD and POOR, counting notes left unplayed after a failure as POOR.
+    pub fn miss_count(&self) -> u32 {
+        self.notes as u32 - (self.pgreat + self.great + self.good)
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::resources::{GaugeType, PlayResult};
+
+const SCORE_STORE_PATH: &str = "./scores.ron";
+
+/// Clear lamps from worst to best.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ClearLamp {
+    #[default]
+    NoPlay,
+    Failed,
+    Assist,
+    Easy,
+    Clear,
+    Hard,
+    ExHard,
+    FullCombo,
+}
+
+impl ClearLamp {
+    pub fn new(result: &PlayResult) -> Self {
+        if !result.cleared {
+            return ClearLamp::Failed;
+        }
+        if result.notes > 0 && result.miss_count() == 0 {
+            return ClearLamp::FullCombo;
+        }
+        match result.gauge {
+            GaugeType::AssistEasy => ClearLamp::Assist,
+            GaugeType::Easy => ClearLamp::Easy,
+            GaugeType::Normal => ClearLamp::Clear,
+            GaugeType::Hard => ClearLamp::Hard,
+            GaugeType::ExHard | GaugeType::Hazard => ClearLamp::ExHard,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ClearLamp::NoPlay => "NO PLAY",
+            ClearLamp::Failed => "FAILED",
+            ClearLamp::Assist => "ASSIST CLEAR",
+            ClearLamp::Easy => "EASY CLEAR",
+            ClearLamp::Clear => "CLEAR",
+            ClearLamp::Hard => "HARD CLEAR",
+            ClearLamp::ExHard => "EX-HARD CLEAR",
+            ClearLamp::FullCombo => "FULL COMBO",
+        }
+    }
+
+    pub fn color(self) -> Color {
+        match self {
+            ClearLamp::NoPlay => Color::srgb(0.2, 0.2, 0.2),
+            ClearLamp::Failed => Color::srgb(0.5, 0., 0.),
+            ClearLamp::Assist => Color::srgb(0.6, 0.3, 1.),
+            ClearLamp::Easy => Color::srgb(0.3, 1., 0.3),
+            ClearLamp::Clear => Color::srgb(0.3, 0.8, 1.),
+            ClearLamp::Hard => Color::srgb(1., 0.2, 0.2),
+            ClearLamp::ExHard => Color::srgb(1., 1., 0.2),
+            ClearLamp::FullCombo => Color::srgb(1., 1., 1.),
+        }
+    }
+}
+
+/// Best results of one chart.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChartRecord {
+    pub best_ex_score: u32,
+    pub max_ex_score: u32,
+    pub best_miss_count: Option<u32>,
+    pub max_combo: u32,
+    pub lamp: ClearLamp,
+    pub play_count: u32,
+    /// Unix time in seconds.
+    pub last_played: u64,
+}
+
+/// Records of every played chart keyed by chart hash, saved to `./scores.ron`.
+#[derive(Resource, Debug, Default, Serialize, Deserialize)]
+pub struct ScoreStore {
+    records: BTreeMap<String, ChartRecord>,
+}
+
+impl ScoreStore {
+    pub fn load() -> Self {
+        let Ok(text) = fs::read_to_string(SCORE_STORE_PATH) else {
+            return Self::default();
+        };
+        ron::from_str(&text).unwrap_or_else(|err| {
+            warn!("failed to read {}: {}", SCORE_STORE_PATH, err);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self) {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).unwrap();
+        if let Err(err) = fs::write(SCORE_STORE_PATH, text) {
+            warn!("failed to write {}: {}", SCORE_STORE_PATH, err);
+        }
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&ChartRecord> {
+        self.records.get(hash)
+    }
+
+    /// Merges `result` into the record of the chart, keeping the best of each field.
+    pub fn record(&mut self, hash: &str, result: &PlayResult) {
+        let record = self.records.entry(hash.to_string()).or_default();
+        record.best_ex_score = record.best_ex_score.max(result.ex_score());
+        record.max_ex_score = result.max_ex_score();
+        record.best_miss_count = Some(
+            record
+                .best_miss_count
+                .map_or(result.miss_count(), |best| best.min(result.miss_count())),
+        );
+        record.max_combo = record.max_combo.max(result.max_combo);
+        record.lamp = record.lamp.max(ClearLamp::new(result));
+        record.play_count += 1;
+        record.last_played = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(gauge: GaugeType, cleared: bool, pgreat: u32, great: u32, poor: u32) -> PlayResult {
+        PlayResult {
+            gauge,
+            cleared,
+            notes: (pgreat + great + poor) as usize,
+            pgreat,
+            great,
+            good: 0,
+            bad: 0,
+            poor,
+            empty_poor: 0,
+            max_combo: pgreat + great,
+            fast: 0,
+            slow: 0,
+            offsets: vec![],
+            gauge_history: vec![],
+            watched: false,
+        }
+    }
+
+    #[test]
+    fn lamps() {
+        let lamp = |gauge, cleared| ClearLamp::new(&result(gauge, cleared, 50, 40, 10));
+        assert_eq!(lamp(GaugeType::Hard, false), ClearLamp::Failed);
+        assert_eq!(lamp(GaugeType::AssistEasy, true), ClearLamp::Assist);
+        assert_eq!(lamp(GaugeType::Easy, true), ClearLamp::Easy);
+        assert_eq!(lamp(GaugeType::Normal, true), ClearLamp::Clear);
+        assert_eq!(lamp(GaugeType::Hard, true), ClearLamp::Hard);
+        assert_eq!(lamp(GaugeType::ExHard, true), ClearLamp::ExHard);
+        assert_eq!(lamp(GaugeType::Hazard, true), ClearLamp::ExHard);
+
+        let full_combo = result(GaugeType::Easy, true, 90, 10, 0);
+        assert_eq!(ClearLamp::new(&full_combo), ClearLamp::FullCombo);
+        let failed_full_combo = result(GaugeType::Normal, false, 90, 10, 0);
+        assert_eq!(ClearLamp::new(&failed_full_combo), ClearLamp::Failed);
+    }
+
+    #[test]
+    fn worse_play_keeps_the_record() {
+        let mut store = ScoreStore::default();
+        store.record("chart", &result(GaugeType::Hard, true, 80, 15, 5));
+        store.record("chart", &result(GaugeType::Easy, false, 10, 20, 70));
+
+        let record = store.get("chart").unwrap();
+        assert_eq!(record.lamp, ClearLamp::Hard);
+        assert_eq!(record.best_ex_score, 175);
+        assert_eq!(record.max_ex_score, 200);
+        assert_eq!(record.best_miss_count, Some(5));
+        assert_eq!(record.max_combo, 95);
+        assert_eq!(record.play_count, 2);
+    }
+
+    #[test]
+    fn better_play_updates_the_record() {
+        let mut store = ScoreStore::default();
+        store.record("chart", &result(GaugeType::Normal, false, 10, 20, 70));
+        store.record("chart", &result(GaugeType::Easy, true, 80, 15, 5));
+
+        let record = store.get("chart").unwrap();
+        assert_eq!(record.lamp, ClearLamp::Easy);
+        assert_eq!(record.best_ex_score, 175);
+        assert_eq!(record.best_miss_count, Some(5));
+        assert_eq!(record.max_combo, 95);
+        assert!(store.get("other").is_none());
+    }
+}
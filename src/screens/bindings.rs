@@ -0,0 +1,447 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+
+use bevy::input::ButtonState;
+use bevy::input::gamepad::GamepadButtonStateChangedEvent;
+use bevy::input::keyboard::KeyboardInput;
+use bevy::{ecs::system::SystemParam, prelude::*};
+use serde::de::{EnumAccess, IntoDeserializer, VariantAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use super::gameplay::Lane;
+
+const KEY_BINDINGS_PATH: &str = "./keys.ron";
+
+/// Keys and buttons bound to each lane, any of them presses the lane.
+pub(super) type LaneKeys = BTreeMap<Lane, Vec<Binding>>;
+
+/// A keyboard key or a button on any connected gamepad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub(super) enum Binding {
+    Key(KeyCode),
+    Pad(GamepadButton),
+}
+
+/// Reads `Key(KeyA)` and `Pad(South)`, and also a bare `KeyA` as bindings files only held
+/// keyboard keys before gamepads could be bound.
+impl<'de> Deserialize<'de> for Binding {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BindingVisitor;
+
+        impl<'de> Visitor<'de> for BindingVisitor {
+            type Value = Binding;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a key or gamepad button")
+            }
+
+            fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Binding, A::Error> {
+                let (VariantName(name), variant) = data.variant()?;
+                match name.as_str() {
+                    "Key" => variant.newtype_variant().map(Binding::Key),
+                    "Pad" => variant.newtype_variant().map(Binding::Pad),
+                    key => {
+                        variant.unit_variant()?;
+                        KeyCode::deserialize(key.into_deserializer()).map(Binding::Key)
+                    }
+                }
+            }
+        }
+
+        deserializer.deserialize_enum("Binding", &["Key", "Pad"], BindingVisitor)
+    }
+}
+
+/// An enum variant's name, which RON only hands out as an identifier.
+struct VariantName(String);
+
+impl<'de> Deserialize<'de> for VariantName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct NameVisitor;
+
+        impl Visitor<'_> for NameVisitor {
+            type Value = VariantName;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a variant name")
+            }
+
+            fn visit_str<E>(self, name: &str) -> Result<VariantName, E> {
+                Ok(VariantName(name.to_string()))
+            }
+        }
+
+        deserializer.deserialize_identifier(NameVisitor)
+    }
+}
+
+impl Binding {
+    /// Short name for on-screen display, e.g. `A` for `KeyA`.
+    pub fn label(self) -> String {
+        let name = match self {
+            Binding::Key(key) => format!("{:?}", key),
+            Binding::Pad(button) => format!("PAD {:?}", button),
+        };
+        name.strip_prefix("Key")
+            .or_else(|| name.strip_prefix("Digit"))
+            .unwrap_or(&name)
+            .to_uppercase()
+    }
+}
+
+/// Reads `Binding`s from the keyboard and all gamepads.
+#[derive(SystemParam)]
+pub(super) struct BindingInput<'w, 's> {
+    keys: Res<'w, ButtonInput<KeyCode>>,
+    gamepads: Query<'w, 's, &'static Gamepad>,
+}
+
+impl BindingInput<'_, '_> {
+    pub fn just_pressed(&self, binding: Binding) -> bool {
+        match binding {
+            Binding::Key(key) => self.keys.just_pressed(key),
+            Binding::Pad(button) => self.gamepads.iter().any(|pad| pad.just_pressed(button)),
+        }
+    }
+
+    pub fn pressed(&self, binding: Binding) -> bool {
+        match binding {
+            Binding::Key(key) => self.keys.pressed(key),
+            Binding::Pad(button) => self.gamepads.iter().any(|pad| pad.pressed(button)),
+        }
+    }
+
+    pub fn any_just_pressed(&self, bindings: &[Binding]) -> bool {
+        bindings.iter().any(|binding| self.just_pressed(*binding))
+    }
+
+    pub fn any_pressed(&self, bindings: &[Binding]) -> bool {
+        bindings.iter().any(|binding| self.pressed(*binding))
+    }
+
+    /// The first key or button pressed this frame, keyboard first.
+    pub fn get_just_pressed(&self) -> Option<Binding> {
+        let key = self
+            .keys
+            .get_just_pressed()
+            .next()
+            .copied()
+            .map(Binding::Key);
+        key.or_else(|| {
+            self.gamepads
+                .iter()
+                .find_map(|pad| pad.get_just_pressed().next().copied())
+                .map(Binding::Pad)
+        })
+    }
+}
+
+/// Reads `Binding` changes from the keyboard and gamepad events, rather than the per-frame
+/// state `BindingInput` sees, so quick taps within one frame aren't lost.
+#[derive(SystemParam)]
+pub(super) struct BindingEvents<'w, 's> {
+    keyboard: MessageReader<'w, 's, KeyboardInput>,
+    buttons: MessageReader<'w, 's, GamepadButtonStateChangedEvent>,
+}
+
+impl BindingEvents<'_, '_> {
+    /// Each key or button that went down or up since the last read, and whether it went down.
+    /// Keys come before buttons, key repeats are skipped.
+    pub fn read(&mut self) -> Vec<(Binding, bool)> {
+        self.keyboard
+            .read()
+            .filter(|event| !event.repeat)
+            .map(|event| {
+                (
+                    Binding::Key(event.key_code),
+                    event.state == ButtonState::Pressed,
+                )
+            })
+            .chain(self.buttons.read().map(|event| {
+                (
+                    Binding::Pad(event.button),
+                    event.state == ButtonState::Pressed,
+                )
+            }))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct SideBindings {
+    /// Scratch on the left.
+    pub p1: LaneKeys,
+    /// Scratch on the right.
+    pub p2: LaneKeys,
+}
+
+/// Turns a controller's turntable axis into scratch presses.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub(super) struct TurntableSettings {
+    pub axis: GamepadAxis,
+    /// Multiplies the axis movement before it is compared against `deadzone`.
+    pub sensitivity: f32,
+    /// Smallest movement between two readings that counts as turning.
+    pub deadzone: f32,
+    /// Seconds the scratch stays pressed after the turntable stops turning.
+    pub hold: f32,
+}
+
+impl Default for TurntableSettings {
+    fn default() -> Self {
+        Self {
+            axis: GamepadAxis::LeftStickX,
+            sensitivity: 1.,
+            deadzone: 0.005,
+            hold: 0.1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) enum MouseAxis {
+    X,
+    Y,
+}
+
+/// Scratches LS with raw mouse movement, for keyboard players.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct MouseTurntableSettings {
+    pub enabled: bool,
+    pub axis: MouseAxis,
+    /// Smallest movement between two readings, in raw mouse counts, that counts as turning.
+    pub threshold: f32,
+    /// Seconds the scratch stays pressed after the mouse stops moving.
+    pub hold: f32,
+}
+
+impl Default for MouseTurntableSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            axis: MouseAxis::X,
+            threshold: 1.,
+            hold: 0.1,
+        }
+    }
+}
+
+/// Lane and meta key bindings, saved to `./keys.ron`.
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+pub(super) struct KeyBindings {
+    /// Starts the chart on select and the play once it is loaded.
+    pub start: Vec<Binding>,
+    /// Leaves gameplay back to select.
+    pub select: Vec<Binding>,
+    pub beat_5k: SideBindings,
+    pub beat_7k: SideBindings,
+    pub double_10k: LaneKeys,
+    pub double_14k: LaneKeys,
+    /// Turntable of the first gamepad scratches LS, the second RS.
+    #[serde(default)]
+    pub turntable: TurntableSettings,
+    #[serde(default)]
+    pub mouse_turntable: MouseTurntableSettings,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            start: vec![
+                Binding::Key(KeyCode::Enter),
+                Binding::Key(KeyCode::KeyY),
+                Binding::Pad(GamepadButton::Start),
+            ],
+            select: vec![
+                Binding::Key(KeyCode::Escape),
+                Binding::Pad(GamepadButton::Select),
+            ],
+            beat_5k: SideBindings {
+                p1: lane_keys(FIVE_KEY_LANE_MAP, &PAD_LANE_MAP[..5]),
+                p2: lane_keys(SP_2P_FIVE_KEY_LANE_MAP, &PAD_LANE_MAP[..5]),
+            },
+            beat_7k: SideBindings {
+                p1: lane_keys(KEY_LANE_MAP, PAD_LANE_MAP),
+                p2: lane_keys(SP_2P_KEY_LANE_MAP, PAD_LANE_MAP),
+            },
+            double_10k: lane_keys(DP_FIVE_KEY_LANE_MAP, &[]),
+            double_14k: lane_keys(DP_KEY_LANE_MAP, &[]),
+            turntable: TurntableSettings::default(),
+            mouse_turntable: MouseTurntableSettings::default(),
+        }
+    }
+}
+
+impl KeyBindings {
+    /// Reads `./keys.ron`, writing the defaults there on first run.
+    pub fn load() -> Self {
+        let Ok(text) = fs::read_to_string(KEY_BINDINGS_PATH) else {
+            let bindings = Self::default();
+            bindings.save();
+            return bindings;
+        };
+        ron::from_str(&text).unwrap_or_else(|err| {
+            warn!("failed to read {}: {}", KEY_BINDINGS_PATH, err);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self) {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).unwrap();
+        if let Err(err) = fs::write(KEY_BINDINGS_PATH, text) {
+            warn!("failed to write {}: {}", KEY_BINDINGS_PATH, err);
+        }
+    }
+}
+
+fn lane_keys(keys: &[(KeyCode, Lane)], buttons: &[(GamepadButton, Lane)]) -> LaneKeys {
+    let mut bindings = LaneKeys::new();
+    for (key, lane) in keys {
+        bindings.entry(*lane).or_default().push(Binding::Key(*key));
+    }
+    for (button, lane) in buttons {
+        bindings
+            .entry(*lane)
+            .or_default()
+            .push(Binding::Pad(*button));
+    }
+    bindings
+}
+
+/// Keys 1-7 of an IIDX-style controller as most of them report through gilrs.
+const PAD_LANE_MAP: &[(GamepadButton, Lane)] = &[
+    (GamepadButton::South, Lane::L1),
+    (GamepadButton::East, Lane::L2),
+    (GamepadButton::West, Lane::L3),
+    (GamepadButton::North, Lane::L4),
+    (GamepadButton::LeftTrigger, Lane::L5),
+    (GamepadButton::RightTrigger, Lane::L6),
+    (GamepadButton::LeftTrigger2, Lane::L7),
+];
+
+const KEY_LANE_MAP: &[(KeyCode, Lane)] = &[
+    (KeyCode::KeyA, Lane::LS),
+    (KeyCode::KeyS, Lane::L1),
+    (KeyCode::KeyD, Lane::L2),
+    (KeyCode::KeyF, Lane::L3),
+    (KeyCode::Space, Lane::L4),
+    (KeyCode::KeyJ, Lane::L5),
+    (KeyCode::KeyK, Lane::L6),
+    (KeyCode::KeyL, Lane::L7),
+];
+
+const SP_2P_KEY_LANE_MAP: &[(KeyCode, Lane)] = &[
+    (KeyCode::KeyS, Lane::L1),
+    (KeyCode::KeyD, Lane::L2),
+    (KeyCode::KeyF, Lane::L3),
+    (KeyCode::Space, Lane::L4),
+    (KeyCode::KeyJ, Lane::L5),
+    (KeyCode::KeyK, Lane::L6),
+    (KeyCode::KeyL, Lane::L7),
+    (KeyCode::Semicolon, Lane::LS),
+];
+
+const FIVE_KEY_LANE_MAP: &[(KeyCode, Lane)] = &[
+    (KeyCode::KeyA, Lane::LS),
+    (KeyCode::KeyD, Lane::L1),
+    (KeyCode::KeyF, Lane::L2),
+    (KeyCode::Space, Lane::L3),
+    (KeyCode::KeyJ, Lane::L4),
+    (KeyCode::KeyK, Lane::L5),
+];
+
+const SP_2P_FIVE_KEY_LANE_MAP: &[(KeyCode, Lane)] = &[
+    (KeyCode::KeyD, Lane::L1),
+    (KeyCode::KeyF, Lane::L2),
+    (KeyCode::Space, Lane::L3),
+    (KeyCode::KeyJ, Lane::L4),
+    (KeyCode::KeyK, Lane::L5),
+    (KeyCode::Semicolon, Lane::LS),
+];
+
+const DP_FIVE_KEY_LANE_MAP: &[(KeyCode, Lane)] = &[
+    (KeyCode::ShiftLeft, Lane::LS),
+    (KeyCode::KeyZ, Lane::L1),
+    (KeyCode::KeyS, Lane::L2),
+    (KeyCode::KeyX, Lane::L3),
+    (KeyCode::KeyD, Lane::L4),
+    (KeyCode::KeyC, Lane::L5),
+    (KeyCode::KeyM, Lane::R1),
+    (KeyCode::KeyK, Lane::R2),
+    (KeyCode::Comma, Lane::R3),
+    (KeyCode::KeyL, Lane::R4),
+    (KeyCode::Period, Lane::R5),
+    (KeyCode::ShiftRight, Lane::RS),
+];
+
+const DP_KEY_LANE_MAP: &[(KeyCode, Lane)] = &[
+    (KeyCode::ShiftLeft, Lane::LS),
+    (KeyCode::KeyZ, Lane::L1),
+    (KeyCode::KeyS, Lane::L2),
+    (KeyCode::KeyX, Lane::L3),
+    (KeyCode::KeyD, Lane::L4),
+    (KeyCode::KeyC, Lane::L5),
+    (KeyCode::KeyF, Lane::L6),
+    (KeyCode::KeyV, Lane::L7),
+    (KeyCode::KeyM, Lane::R1),
+    (KeyCode::KeyK, Lane::R2),
+    (KeyCode::Comma, Lane::R3),
+    (KeyCode::KeyL, Lane::R4),
+    (KeyCode::Period, Lane::R5),
+    (KeyCode::Semicolon, Lane::R6),
+    (KeyCode::Slash, Lane::R7),
+    (KeyCode::ShiftRight, Lane::RS),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_keyboard_only_bindings() {
+        let text = "(
+            start: [Enter, KeyY],
+            select: [Escape],
+            beat_5k: (p1: {LS: [KeyA]}, p2: {}),
+            beat_7k: (p1: {L1: [KeyS, KeyZ]}, p2: {}),
+            // 旧版本写入的 9 键设置，读取时忽略
+            popn_9k: {},
+            double_10k: {},
+            double_14k: {},
+        )";
+        let bindings: KeyBindings = ron::from_str(text).unwrap();
+        assert_eq!(
+            bindings.start,
+            [Binding::Key(KeyCode::Enter), Binding::Key(KeyCode::KeyY)]
+        );
+        assert_eq!(
+            bindings.beat_5k.p1[&Lane::LS],
+            [Binding::Key(KeyCode::KeyA)]
+        );
+        assert_eq!(
+            bindings.beat_7k.p1[&Lane::L1],
+            [Binding::Key(KeyCode::KeyS), Binding::Key(KeyCode::KeyZ)]
+        );
+        assert_eq!(bindings.turntable.axis, GamepadAxis::LeftStickX);
+    }
+
+    #[test]
+    fn turntable_hold_defaults_when_missing() {
+        let settings: TurntableSettings =
+            ron::from_str("(axis: RightStickY, sensitivity: 2., deadzone: 0.01)").unwrap();
+        assert_eq!(settings.axis, GamepadAxis::RightStickY);
+        assert_eq!(settings.hold, TurntableSettings::default().hold);
+    }
+
+    #[test]
+    fn saved_bindings_load_back() {
+        let bindings = KeyBindings::default();
+        let text =
+            ron::ser::to_string_pretty(&bindings, ron::ser::PrettyConfig::default()).unwrap();
+        let loaded: KeyBindings = ron::from_str(&text).unwrap();
+        assert_eq!(loaded.start, bindings.start);
+        assert_eq!(loaded.beat_7k.p1, bindings.beat_7k.p1);
+    }
+}
@@ -0,0 +1,218 @@
+use bevy::input::InputSystems;
+use bevy::prelude::*;
+use bevy_kira_audio::prelude::*;
+
+use super::{
+    Screen,
+    bindings::{Binding, BindingEvents, BindingInput, KeyBindings},
+};
+use crate::timing_offsets::TimingOffsets;
+
+const METRONOME_BPM: f32 = 120.;
+/// Seconds from entering the screen to the first click.
+const METRONOME_DELAY: f32 = 1.;
+/// Beats played before taps start counting, to settle into the rhythm.
+const LEAD_IN_BEATS: i32 = 4;
+/// Taps further than this from the nearest beat are ignored as stray presses.
+const MAX_TAP_OFFSET: f32 = 0.2;
+const FLASH_RADIUS: f32 = 80.;
+
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(OnEnter(Screen::Calibration), spawn_calibration)
+        .add_systems(
+            PreUpdate,
+            (keyboard_input, tap_input)
+                .after(InputSystems)
+                .run_if(in_state(Screen::Calibration)),
+        )
+        .add_systems(
+            Update,
+            (play_metronome, flash_beat).run_if(in_state(Screen::Calibration)),
+        )
+        .add_systems(OnExit(Screen::Calibration), cleanup_calibration_screen);
+}
+
+#[derive(Component)]
+struct OnCalibrationScreen;
+
+#[derive(Component)]
+struct CalibrationText;
+
+#[derive(Component)]
+struct BeatFlash;
+
+#[derive(Resource)]
+struct Metronome {
+    click: Handle<AudioSource>,
+    /// `Time` elapsed seconds of beat 0.
+    start: f32,
+    next_beat: i32,
+    /// Seconds from the nearest beat to each counted tap, negative when early.
+    taps: Vec<f32>,
+}
+
+impl Metronome {
+    fn beat_interval() -> f32 {
+        60. / METRONOME_BPM
+    }
+
+    fn beat_time(&self, beat: i32) -> f32 {
+        self.start + beat as f32 * Self::beat_interval()
+    }
+
+    fn mean_ms(&self) -> Option<f32> {
+        if self.taps.is_empty() {
+            return None;
+        }
+        Some(self.taps.iter().sum::<f32>() / self.taps.len() as f32 * 1000.)
+    }
+}
+
+fn spawn_calibration(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    time: Res<Time>,
+    bindings: Res<KeyBindings>,
+) {
+    commands.insert_resource(Metronome {
+        click: asset_server.load("sounds/metronome.wav"),
+        start: time.elapsed_secs() + METRONOME_DELAY,
+        next_beat: 0,
+        taps: vec![],
+    });
+
+    let font = asset_server.load("fonts/KosugiMaru-Regular.ttf");
+    let text_font = |font_size: f32| TextFont {
+        font: font.clone(),
+        font_size,
+        ..default()
+    };
+
+    commands.spawn((
+        Text2d::new("TIMING CALIBRATION"),
+        text_font(60.),
+        Transform::from_translation(Vec3::new(0., 440., 0.)),
+        OnCalibrationScreen,
+    ));
+
+    commands.spawn((
+        Mesh2d(meshes.add(Circle::new(FLASH_RADIUS))),
+        MeshMaterial2d(materials.add(Color::srgba(1., 1., 1., 0.))),
+        Transform::from_translation(Vec3::new(0., 120., 0.)),
+        BeatFlash,
+        OnCalibrationScreen,
+    ));
+
+    commands.spawn((
+        Text2d::new(calibration_text(None, 0)),
+        text_font(40.),
+        Transform::from_translation(Vec3::new(0., -120., 0.)),
+        CalibrationText,
+        OnCalibrationScreen,
+    ));
+
+    let start = bindings.start.first().copied().map(Binding::label);
+    let select = bindings.select.first().copied().map(Binding::label);
+    commands.spawn((
+        Text2d::new(format!(
+            "TAP ANY KEY ON THE CLICK / {} SET JUDGE OFFSET / {} BACK",
+            start.unwrap_or_default(),
+            select.unwrap_or_default()
+        )),
+        text_font(24.),
+        Transform::from_translation(Vec3::new(0., -440., 0.)),
+        OnCalibrationScreen,
+    ));
+}
+
+fn calibration_text(mean_ms: Option<f32>, taps: usize) -> String {
+    match mean_ms {
+        Some(mean) => format!("TAPS {}\nAVERAGE {:+.1}MS", taps, mean),
+        None => format!("TAPS {}\nAVERAGE -", taps),
+    }
+}
+
+/// Clicks on every beat, shifted by the audio offset like the chart's sounds.
+fn play_metronome(
+    time: Res<Time>,
+    offsets: Res<TimingOffsets>,
+    audio: Res<Audio>,
+    mut metronome: ResMut<Metronome>,
+) {
+    while metronome.beat_time(metronome.next_beat) + offsets.audio() <= time.elapsed_secs() {
+        audio.play(metronome.click.clone());
+        metronome.next_beat += 1;
+    }
+}
+
+fn flash_beat(
+    time: Res<Time>,
+    metronome: Res<Metronome>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    flash: Single<&MeshMaterial2d<ColorMaterial>, With<BeatFlash>>,
+) {
+    let beats = (time.elapsed_secs() - metronome.start) / Metronome::beat_interval();
+    let alpha = if beats < 0. { 0. } else { 1. - beats.fract() };
+    if let Some(material) = materials.get_mut(&flash.0) {
+        material.color.set_alpha(alpha);
+    }
+}
+
+/// Leaves with the select key, or with the start key once taps were counted, writing their
+/// average to the judge offset.
+fn keyboard_input(
+    input: BindingInput,
+    bindings: Res<KeyBindings>,
+    metronome: Res<Metronome>,
+    mut offsets: ResMut<TimingOffsets>,
+    mut next_screen: ResMut<NextState<Screen>>,
+) {
+    if input.any_just_pressed(&bindings.select) {
+        next_screen.set(Screen::Select);
+    } else if input.any_just_pressed(&bindings.start)
+        && let Some(mean) = metronome.mean_ms()
+    {
+        offsets.judge_ms = mean.round() as i32;
+        offsets.save();
+        next_screen.set(Screen::Select);
+    }
+}
+
+/// Times taps the same way gameplay times lane presses, by the frame that delivers them, and
+/// stores the offset of each to its nearest beat.
+fn tap_input(
+    time: Res<Time>,
+    mut events: BindingEvents,
+    bindings: Res<KeyBindings>,
+    mut metronome: ResMut<Metronome>,
+    mut text: Single<&mut Text2d, With<CalibrationText>>,
+) {
+    let now = time.elapsed_secs();
+    let mut counted = false;
+    for (binding, pressed) in events.read() {
+        if !pressed || bindings.start.contains(&binding) || bindings.select.contains(&binding) {
+            continue;
+        }
+        let beat = ((now - metronome.start) / Metronome::beat_interval()).round() as i32;
+        let offset = now - metronome.beat_time(beat);
+        if beat >= LEAD_IN_BEATS && offset.abs() <= MAX_TAP_OFFSET {
+            metronome.taps.push(offset);
+            counted = true;
+        }
+    }
+    if counted {
+        text.0 = calibration_text(metronome.mean_ms(), metronome.taps.len());
+    }
+}
+
+fn cleanup_calibration_screen(
+    mut commands: Commands,
+    query: Query<Entity, With<OnCalibrationScreen>>,
+) {
+    for entity in &query {
+        commands.entity(entity).despawn();
+    }
+    commands.remove_resource::<Metronome>();
+}
@@ -1,20 +1,42 @@
 use std::path::{Path, PathBuf};
 use std::{env, fs};
 
+use bevy::ecs::system::SystemParam;
+use bevy::input::InputSystems;
 use bevy::platform::collections::HashMap;
 use bevy::prelude::*;
+use bevy::sprite::Anchor;
 use bevy_kira_audio::prelude::*;
 use bms_rs::bms::model::Bms;
-use bms_rs::bms::{BmsOutput, parse_bms, prelude::KeyLayoutBeat};
-use bms_rs::command::ObjId;
-use encoding_rs::SHIFT_JIS;
+use bms_rs::command::{ObjId, PlayerMode};
 use num_traits::ToPrimitive;
+use serde::{Deserialize, Serialize};
 
-use crate::resources::BmsLib;
+use crate::chart::{Chart, parse_chart};
+use crate::replay::ReplayPlayback;
+use crate::resources::{BmsLib, DemoPlay, PlayOptions, PlayResult, PlaySide};
 use crate::screens::Screen;
+use crate::screens::bindings::{BindingEvents, BindingInput, KeyBindings, LaneKeys};
+
+mod autoplay;
+mod beam;
+mod bga;
+mod bgm;
+mod clock;
+mod gauge;
+mod judgement;
+mod offsets;
+mod pacemaker;
+mod replay;
+mod score;
+mod turntable;
+
+use autoplay::Autoplay;
+use bgm::BgmTrack;
+use clock::SongClock;
+use judgement::{Judge, Judgement, TimingWindow};
 
 const LANE_HEIGHT: f32 = 722.;
-const LANE_WIDTH: f32 = 432.;
 const WHITE_NOTE_HEIGHT: f32 = 12.;
 const WHITE_NOTE_WIDTH: f32 = 52.;
 const BLUE_NOTE_HEIGHT: f32 = 12.;
@@ -29,69 +51,19 @@ const JUDGEMENTLINE_POSITION: Vec2 = Vec2::new(
     0.,
     (1080. / 2.) - (LANE_HEIGHT - JUDGEMENTLINE_THICKNESS / 2.),
 );
-const LEFT_BORDER_POSITION: Vec2 = Vec2::new(
-    -(LANE_WIDTH / 2. + BORDER_THICKNESS / 2.),
-    1080. / 2. - LANE_HEIGHT / 2.,
-);
-const RIGHT_BORDER_POSITION: Vec2 = Vec2::new(
-    LANE_WIDTH / 2. + BORDER_THICKNESS / 2.,
-    1080. / 2. - LANE_HEIGHT / 2.,
-);
+const BORDER_CENTER_Y: f32 = 1080. / 2. - LANE_HEIGHT / 2.;
 const BOTTOM_BORDER_POSITION: Vec2 =
     Vec2::new(0., (1080. / 2.) - (LANE_HEIGHT + BORDER_THICKNESS / 2.));
 
 const NOTE_GAP: f32 = 2.;
-const SCRATCH_L2R_RELATIVE_X: f32 = -(LANE_WIDTH / 2.) + SCRATCH_WIDTH / 2.;
-const NOTE1_L2R_RELATIVE_X: f32 =
-    -(LANE_WIDTH / 2.) + SCRATCH_WIDTH + NOTE_GAP + WHITE_NOTE_WIDTH / 2.;
-const NOTE2_L2R_RELATIVE_X: f32 =
-    -(LANE_WIDTH / 2.) + SCRATCH_WIDTH + NOTE_GAP * 2. + WHITE_NOTE_WIDTH + BLUE_NOTE_WIDTH / 2.;
-const NOTE3_L2R_RELATIVE_X: f32 = -(LANE_WIDTH / 2.)
-    + SCRATCH_WIDTH
-    + NOTE_GAP * 3.
-    + WHITE_NOTE_WIDTH
-    + BLUE_NOTE_WIDTH
-    + WHITE_NOTE_WIDTH / 2.;
-const NOTE4_L2R_RELATIVE_X: f32 = -(LANE_WIDTH / 2.)
-    + SCRATCH_WIDTH
-    + NOTE_GAP * 4.
-    + WHITE_NOTE_WIDTH * 2.
-    + BLUE_NOTE_WIDTH
-    + BLUE_NOTE_WIDTH / 2.;
-const NOTE5_L2R_RELATIVE_X: f32 = -(LANE_WIDTH / 2.)
-    + SCRATCH_WIDTH
-    + NOTE_GAP * 5.
-    + WHITE_NOTE_WIDTH * 2.
-    + BLUE_NOTE_WIDTH * 2.
-    + WHITE_NOTE_WIDTH / 2.;
-const NOTE6_L2R_RELATIVE_X: f32 = -(LANE_WIDTH / 2.)
-    + SCRATCH_WIDTH
-    + NOTE_GAP * 6.
-    + WHITE_NOTE_WIDTH * 3.
-    + BLUE_NOTE_WIDTH * 2.
-    + BLUE_NOTE_WIDTH / 2.;
-const NOTE7_L2R_RELATIVE_X: f32 = -(LANE_WIDTH / 2.)
-    + SCRATCH_WIDTH
-    + NOTE_GAP * 7.
-    + WHITE_NOTE_WIDTH * 3.
-    + BLUE_NOTE_WIDTH * 3.
-    + WHITE_NOTE_WIDTH / 2.;
-
-struct TimingWindow {
-    pgreat: f32,
-    great: f32,
-    good: f32,
-    bad: f32,
-    poor: f32,
-}
-
-const TIMING_WINDOW: TimingWindow = TimingWindow {
-    pgreat: 21. / 1000.,
-    great: 60. / 1000.,
-    good: 120. / 1000.,
-    bad: 200. / 1000.,
-    poor: 1000. / 1000.,
-};
+const LANE_GROUP_GAP: f32 = 240.;
+
+/// BPM of charts that declare none.
+const DEFAULT_BPM: f32 = 130.;
+/// Seconds the chart keeps running after its last note or BGM event.
+const CHART_END_TAIL: f32 = 2.;
+/// Seconds to stay on the lanes after the play ends before showing the result.
+const RESULT_DELAY: f32 = 2.;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Segment {
@@ -133,41 +105,284 @@ pub(super) fn plugin(app: &mut App) {
     app.add_systems(
         OnEnter(Screen::Gameplay),
         (
-            spawn_judgement_line,
-            spawn_lane_border,
-            spawn_notes.after(spawn_lanes),
-            spawn_lanes,
+            load_chart,
+            (
+                load_keysounds,
+                spawn_judgement_line,
+                spawn_lane_border,
+                spawn_notes.after(spawn_lanes),
+                spawn_lanes,
+                spawn_bar_lines.after(spawn_notes),
+                spawn_timing_markers
+                    .after(spawn_notes)
+                    .run_if(|options: Res<PlayOptions>| options.timing_markers),
+            )
+                .in_set(ChartSetup),
         ),
     )
-    .add_systems(Update, (notes_fall).run_if(in_state(AppState::Playing)))
-    .add_systems(FixedUpdate, play_bgm.run_if(in_state(AppState::Playing)))
+    .configure_sets(
+        OnEnter(Screen::Gameplay),
+        ChartSetup
+            .after(load_chart)
+            .run_if(resource_exists::<BmsData>),
+    )
+    .add_systems(
+        Update,
+        (
+            (judge_input, update_held_lanes).chain(),
+            // 先判定本帧的按键，再把错过的音符算作 POOR
+            (
+                notes_fall,
+                lane_markers_fall,
+                check_chart_end,
+                update_keysound,
+            )
+                .after(judge_input),
+        )
+            .run_if(in_state(AppState::Playing)),
+    )
+    .add_systems(OnEnter(AppState::Finished), store_result)
+    .add_systems(Update, show_result.run_if(in_state(AppState::Finished)))
+    .add_systems(OnExit(Screen::Gameplay), cleanup_gameplay_screen)
     .add_systems(
-        FixedUpdate,
-        keyboard_input.run_if(in_state(Screen::Gameplay)),
+        PreUpdate,
+        (
+            keyboard_input,
+            // 回放和自动演奏时忽略按键
+            lane_input.run_if(
+                resource_exists::<LaneLayout>
+                    .and(not(resource_exists::<ReplayPlayback>))
+                    .and(not(resource_exists::<Autoplay>)),
+            ),
+        )
+            .after(InputSystems)
+            .run_if(in_state(Screen::Gameplay)),
     )
-    .insert_resource(Time::<Fixed>::from_hz(1000.0))
-    .insert_resource(KeySound {
-        lane_keysound: [ObjId::null(); 8],
-    })
-    .add_plugins(AudioPlugin)
+    .add_message::<LaneInput>()
+    .add_plugins((
+        AudioPlugin,
+        autoplay::plugin,
+        beam::plugin,
+        bga::plugin,
+        bgm::plugin,
+        clock::plugin,
+        gauge::plugin,
+        judgement::plugin,
+        offsets::plugin,
+        pacemaker::plugin,
+        replay::plugin,
+        score::plugin,
+        turntable::plugin,
+    ))
     .insert_state(AppState::Loading);
 }
 
+/// Systems entering gameplay that build the play from the chart `load_chart` read, skipped
+/// when it could not be read.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+struct ChartSetup;
+
+/// The sound each lane plays when pressed without a note to judge, indexed by `Lane`.
 #[derive(Resource)]
 struct KeySound {
-    lane_keysound: [ObjId; 8],
+    lane_keysound: [ObjId; 16],
+}
+
+/// Lanes currently held down, indexed by `Lane`.
+#[derive(Resource, Default)]
+struct HeldLanes([bool; 16]);
+
+/// Plays the keysounds of lane presses.
+#[derive(SystemParam)]
+struct Keysounds<'w> {
+    audio: Res<'w, Audio>,
+    audio_assets: Res<'w, AudioAssets>,
+    key_sound: Res<'w, KeySound>,
+    autoplay: Option<Res<'w, Autoplay>>,
+}
+
+impl Keysounds<'_> {
+    fn play_note(&self, note: &Note) {
+        // 自动演奏的按键音已经混在 BGM 音轨里
+        if self.autoplay.is_none()
+            && let Some(handle) = self.audio_assets.map.get(&note.wav_file)
+        {
+            self.audio.play(handle.clone());
+        }
+    }
+
+    /// The lane's current keysound, for a press without a note to judge.
+    fn play_lane(&self, lane: Lane) {
+        let keysound = &self.key_sound.lane_keysound[lane as usize];
+        if let Some(handle) = self.audio_assets.map.get(keysound) {
+            self.audio.play(handle.clone());
+        }
+    }
 }
 
 #[derive(Resource)]
 struct PlayStatus {
     green_number: u32,
     bpm: f32,
-    start_time: f32,
+    /// Song time the play ends at.
+    end_time: f32,
+    finish_time: f32,
 }
 
+#[derive(Component)]
+struct OnGameplayScreen;
+
 #[derive(Resource)]
 struct BmsData {
     data: Bms,
+    /// Values drawn for the chart's `#RANDOM` blocks this play.
+    random_values: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) enum PlayStyle {
+    /// Single play with the scratch on the left.
+    Single1P,
+    /// Single play with the scratch on the right.
+    Single2P,
+    /// Double play, 1P lanes on the left group and 2P lanes on the right group.
+    Double,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) enum KeyMode {
+    Beat5K,
+    Beat7K,
+    Beat10K,
+    Beat14K,
+}
+
+impl KeyMode {
+    fn keys_per_side(self) -> usize {
+        match self {
+            KeyMode::Beat5K | KeyMode::Beat10K => 5,
+            KeyMode::Beat7K | KeyMode::Beat14K => 7,
+        }
+    }
+}
+
+pub(super) struct LaneColumn {
+    pub(super) lane: Lane,
+    pub(super) x: f32,
+    pub(super) width: f32,
+}
+
+struct LaneGroup {
+    center_x: f32,
+    width: f32,
+}
+
+#[derive(Resource)]
+pub(super) struct LaneLayout {
+    style: PlayStyle,
+    mode: KeyMode,
+    groups: Vec<LaneGroup>,
+    pub(super) columns: Vec<LaneColumn>,
+}
+
+impl LaneLayout {
+    pub(super) fn new(style: PlayStyle, mode: KeyMode) -> Self {
+        let keys = mode.keys_per_side();
+        // 每组从左到右的 lane
+        let side = |lanes: &[Lane], scratch_right: bool| -> Vec<Lane> {
+            let (scratch, keys) = lanes[..=keys].split_first().unwrap();
+            let mut ordered = keys.to_vec();
+            if scratch_right {
+                ordered.push(*scratch);
+            } else {
+                ordered.insert(0, *scratch);
+            }
+            ordered
+        };
+        let p1_lanes = &Lane::all()[..8];
+        let p2_lanes = &Lane::all()[8..];
+
+        let sides = match style {
+            PlayStyle::Single1P => vec![side(p1_lanes, false)],
+            PlayStyle::Single2P => vec![side(p1_lanes, true)],
+            PlayStyle::Double => vec![side(p1_lanes, false), side(p2_lanes, true)],
+        };
+
+        let width = group_width(&sides[0]);
+        let center_xs: &[f32] = if sides.len() == 1 {
+            &[0.]
+        } else {
+            let offset = width / 2. + BORDER_THICKNESS + LANE_GROUP_GAP / 2.;
+            &[-offset, offset]
+        };
+
+        let mut groups = vec![];
+        let mut columns = vec![];
+        for (lanes, center_x) in sides.into_iter().zip(center_xs) {
+            let mut left = center_x - width / 2.;
+            for lane in lanes {
+                let lane_width = lane.note_width();
+                columns.push(LaneColumn {
+                    lane,
+                    x: left + lane_width / 2.,
+                    width: lane_width,
+                });
+                left += lane_width + NOTE_GAP;
+            }
+            groups.push(LaneGroup {
+                center_x: *center_x,
+                width,
+            });
+        }
+
+        Self {
+            style,
+            mode,
+            groups,
+            columns,
+        }
+    }
+
+    /// Index into `groups` of the group drawing `lane`.
+    fn group_index(&self, lane: Lane) -> usize {
+        match self.style {
+            PlayStyle::Double if lane.is_2p() => 1,
+            _ => 0,
+        }
+    }
+
+    fn column(&self, lane: Lane) -> Option<&LaneColumn> {
+        self.columns.iter().find(|column| column.lane == lane)
+    }
+
+    /// The bindings for this layout's key mode and side.
+    pub(super) fn lane_keys<'a>(&self, bindings: &'a KeyBindings) -> &'a LaneKeys {
+        match (self.mode, self.style) {
+            (KeyMode::Beat5K, PlayStyle::Single2P) => &bindings.beat_5k.p2,
+            (KeyMode::Beat5K, _) => &bindings.beat_5k.p1,
+            (KeyMode::Beat7K, PlayStyle::Single2P) => &bindings.beat_7k.p2,
+            (KeyMode::Beat7K, _) => &bindings.beat_7k.p1,
+            (KeyMode::Beat10K, _) => &bindings.double_10k,
+            (KeyMode::Beat14K, _) => &bindings.double_14k,
+        }
+    }
+
+    pub(super) fn lane_keys_mut<'a>(&self, bindings: &'a mut KeyBindings) -> &'a mut LaneKeys {
+        match (self.mode, self.style) {
+            (KeyMode::Beat5K, PlayStyle::Single2P) => &mut bindings.beat_5k.p2,
+            (KeyMode::Beat5K, _) => &mut bindings.beat_5k.p1,
+            (KeyMode::Beat7K, PlayStyle::Single2P) => &mut bindings.beat_7k.p2,
+            (KeyMode::Beat7K, _) => &mut bindings.beat_7k.p1,
+            (KeyMode::Beat10K, _) => &mut bindings.double_10k,
+            (KeyMode::Beat14K, _) => &mut bindings.double_14k,
+        }
+    }
+}
+
+/// The columns of `lanes` side by side, with a gap between each column.
+fn group_width(lanes: &[Lane]) -> f32 {
+    lanes.iter().map(|lane| lane.note_width()).sum::<f32>()
+        + NOTE_GAP * (lanes.len() - 1) as f32
 }
 
 #[derive(Resource)]
@@ -181,8 +396,8 @@ struct JudgementLine;
 #[derive(Component)]
 struct LaneBorder;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-enum Lane {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub(super) enum Lane {
     LS,
     L1,
     L2,
@@ -191,6 +406,14 @@ enum Lane {
     L5,
     L6,
     L7,
+    RS,
+    R1,
+    R2,
+    R3,
+    R4,
+    R5,
+    R6,
+    R7,
 }
 
 impl Lane {
@@ -204,12 +427,74 @@ impl Lane {
             Lane::L5,
             Lane::L6,
             Lane::L7,
+            Lane::RS,
+            Lane::R1,
+            Lane::R2,
+            Lane::R3,
+            Lane::R4,
+            Lane::R5,
+            Lane::R6,
+            Lane::R7,
         ]
     }
+
+    fn is_2p(self) -> bool {
+        matches!(
+            self,
+            Lane::RS | Lane::R1 | Lane::R2 | Lane::R3 | Lane::R4 | Lane::R5 | Lane::R6 | Lane::R7
+        )
+    }
+
+    fn note_width(self) -> f32 {
+        match self.key_number() {
+            None => SCRATCH_WIDTH,
+            Some(n) if n % 2 == 0 => BLUE_NOTE_WIDTH,
+            Some(_) => WHITE_NOTE_WIDTH,
+        }
+    }
+
+    /// Key number counted from the left of its lane group, `None` for scratch.
+    pub(super) fn key_number(self) -> Option<usize> {
+        match self {
+            Lane::LS | Lane::RS => None,
+            Lane::L1 | Lane::R1 => Some(1),
+            Lane::L2 | Lane::R2 => Some(2),
+            Lane::L3 | Lane::R3 => Some(3),
+            Lane::L4 | Lane::R4 => Some(4),
+            Lane::L5 | Lane::R5 => Some(5),
+            Lane::L6 | Lane::R6 => Some(6),
+            Lane::L7 | Lane::R7 => Some(7),
+        }
+    }
 }
 
+/// Channel ids (as base62 `u16`) of the visible note channels.
+const CHANNEL_LANE_MAP: &[(u16, Lane)] = &[
+    // 1P: 11-15, 16 scratch, 18-19
+    (63, Lane::L1),
+    (64, Lane::L2),
+    (65, Lane::L3),
+    (66, Lane::L4),
+    (67, Lane::L5),
+    (68, Lane::LS),
+    (70, Lane::L6),
+    (71, Lane::L7),
+    // 2P: 21-25, 26 scratch, 28-29
+    (125, Lane::R1),
+    (126, Lane::R2),
+    (127, Lane::R3),
+    (128, Lane::R4),
+    (129, Lane::R5),
+    (130, Lane::RS),
+    (132, Lane::R6),
+    (133, Lane::R7),
+];
+
+const BGM_CHANNEL: u16 = 1;
+
 #[derive(Component)]
 struct Note {
+    lane: Lane,
     time: f32,
     wav_file: ObjId,
     real_crotchet: f32,
@@ -227,72 +512,91 @@ struct BPMEvent {
     time: f32,
 }
 
+/// A measure bar line, or a BPM change / stop marker, scrolling with the notes.
+#[derive(Component)]
+struct LaneMarker {
+    time: f32,
+}
+
 fn spawn_judgement_line(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
+    layout: Res<LaneLayout>,
 ) {
-    let mesh = meshes.add(Rectangle::new(LANE_WIDTH + 4., 4.));
     let material = materials.add(Color::srgb(1., 0., 0.));
 
-    commands.spawn((
-        JudgementLine,
-        Mesh2d(mesh.clone()),
-        MeshMaterial2d(material.clone()),
-        Transform::from_translation(JUDGEMENTLINE_POSITION.extend(0.)),
-    ));
+    for group in &layout.groups {
+        commands.spawn((
+            JudgementLine,
+            OnGameplayScreen,
+            Mesh2d(meshes.add(Rectangle::new(group.width + 4., 4.))),
+            MeshMaterial2d(material.clone()),
+            Transform::from_translation(
+                (JUDGEMENTLINE_POSITION + Vec2::new(group.center_x, 0.)).extend(0.),
+            ),
+        ));
+    }
 }
 
 fn spawn_lane_border(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
-    asset_server: Res<AssetServer>,
+    layout: Res<LaneLayout>,
 ) {
     let color = materials.add(Color::srgb(1., 1., 1.));
 
-    commands
-        .spawn((
-            LaneBorder,
-            Transform::default(),
-            GlobalTransform::default(),
-            Visibility::default(),
-            InheritedVisibility::default(),
-        ))
-        .with_children(|parent| {
-            // 下
-            parent.spawn((
-                Mesh2d(meshes.add(Rectangle::new(
-                    LANE_WIDTH + BORDER_THICKNESS * 2.,
-                    BORDER_THICKNESS,
-                ))),
-                MeshMaterial2d(color.clone()),
-                Transform::from_translation(BOTTOM_BORDER_POSITION.extend(0.)),
-            ));
+    for group in &layout.groups {
+        let side_border_x = group.width / 2. + BORDER_THICKNESS / 2.;
+
+        commands
+            .spawn((
+                LaneBorder,
+                OnGameplayScreen,
+                Transform::from_translation(Vec3::new(group.center_x, 0., 0.)),
+                GlobalTransform::default(),
+                Visibility::default(),
+                InheritedVisibility::default(),
+            ))
+            .with_children(|parent| {
+                // 下
+                parent.spawn((
+                    Mesh2d(meshes.add(Rectangle::new(
+                        group.width + BORDER_THICKNESS * 2.,
+                        BORDER_THICKNESS,
+                    ))),
+                    MeshMaterial2d(color.clone()),
+                    Transform::from_translation(BOTTOM_BORDER_POSITION.extend(0.)),
+                ));
 
-            // 左
-            parent.spawn((
-                Mesh2d(meshes.add(Rectangle::new(BORDER_THICKNESS, LANE_HEIGHT))),
-                MeshMaterial2d(color.clone()),
-                Transform::from_translation(LEFT_BORDER_POSITION.extend(0.)),
-            ));
+                // 左
+                parent.spawn((
+                    Mesh2d(meshes.add(Rectangle::new(BORDER_THICKNESS, LANE_HEIGHT))),
+                    MeshMaterial2d(color.clone()),
+                    Transform::from_translation(Vec3::new(-side_border_x, BORDER_CENTER_Y, 0.)),
+                ));
 
-            // 右
-            parent.spawn((
-                Mesh2d(meshes.add(Rectangle::new(BORDER_THICKNESS, LANE_HEIGHT))),
-                MeshMaterial2d(color.clone()),
-                Transform::from_translation(RIGHT_BORDER_POSITION.extend(0.)),
-            ));
-        });
+                // 右
+                parent.spawn((
+                    Mesh2d(meshes.add(Rectangle::new(BORDER_THICKNESS, LANE_HEIGHT))),
+                    MeshMaterial2d(color.clone()),
+                    Transform::from_translation(Vec3::new(side_border_x, BORDER_CENTER_Y, 0.)),
+                ));
+            });
+    }
 }
 
-fn find_wav(path: &str) -> Option<PathBuf> {
+const AUDIO_EXTS: &[&str] = &["wav", "ogg"];
+const IMAGE_EXTS: &[&str] = &["bmp", "png", "jpg", "jpeg"];
+
+/// Finds the file with the same stem as `path` and one of `exts`,
+/// as charts often reference `.wav`/`.bmp` while shipping `.ogg`/`.png`.
+fn find_file(path: &str, exts: &[&str]) -> Option<PathBuf> {
     let path = Path::new(path);
     let parent = path.parent()?;
     let stem = path.file_stem()?.to_string_lossy();
 
-    let audio_exts = ["wav", "ogg"];
-
     if let Ok(entries) = fs::read_dir(parent) {
         for entry in entries.flatten() {
             let file_name_os = entry.file_name();
@@ -302,7 +606,7 @@ fn find_wav(path: &str) -> Option<PathBuf> {
 
             if file_name.starts_with(&*stem) {
                 if let Some(ext) = file_path.extension().and_then(|e| e.to_str()) {
-                    if audio_exts.contains(&ext.to_lowercase().as_str()) {
+                    if exts.contains(&ext.to_lowercase().as_str()) {
                         return Some(file_path);
                     }
                 }
@@ -320,6 +624,7 @@ fn spawn_lanes(mut commands: Commands) {
     for lane in Lane::all() {
         commands.spawn((
             Lanes(*lane),
+            OnGameplayScreen,
             Transform::default(),
             GlobalTransform::default(),
         ));
@@ -517,37 +822,124 @@ impl Crotchet {
     }
 }
 
-fn spawn_notes(
+fn load_chart(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-    asset_server: Res<AssetServer>,
-    lib: ResMut<BmsLib>,
-    lane_query: Query<(Entity, &Lanes)>,
+    lib: Res<BmsLib>,
+    options: Res<PlayOptions>,
+    playback: Option<Res<ReplayPlayback>>,
+    mut next_screen: ResMut<NextState<Screen>>,
 ) {
-    let bytes = std::fs::read(lib.cursor_entry().unwrap().path.clone()).unwrap();
-    let (source, _encoding_used, _had_errors) = SHIFT_JIS.decode(&bytes);
-    let source = source.into_owned();
-    let BmsOutput { bms, .. }: BmsOutput<KeyLayoutBeat> = parse_bms(&source);
+    let path = &lib.cursor_entry().unwrap().path;
+    let seed = playback.map_or(options.random_seed, |playback| playback.replay.random_seed);
+    let Chart {
+        bms,
+        random_values,
+        judge_rank,
+    } = match parse_chart(path, seed) {
+        Ok(chart) => chart,
+        Err(err) => {
+            // 读不了就回到选曲，没有 BmsData 时 ChartSetup 里的系统都不会运行
+            warn!("failed to read {}: {}", path.display(), err);
+            next_screen.set(Screen::Select);
+            return;
+        }
+    };
+    info!("random seed: {}, values: {:?}", seed, random_values);
+
+    let has_2p_notes = bms.notes.all_notes().any(|wav_obj| {
+        lane_of_channel(wav_obj.channel_id.as_u16()).is_some_and(|lane| lane.is_2p())
+    });
+    let style = if bms.header.player == Some(PlayerMode::Double) || has_2p_notes {
+        PlayStyle::Double
+    } else {
+        match options.side {
+            PlaySide::P1 => PlayStyle::Single1P,
+            PlaySide::P2 => PlayStyle::Single2P,
+        }
+    };
+
+    let mode = detect_key_mode(&bms, path, style);
+
+    commands.insert_resource(LaneLayout::new(style, mode));
+    commands.insert_resource(TimingWindow::for_rank(judge_rank));
+    commands.insert_resource(BmsData {
+        data: bms,
+        random_values,
+    });
+    commands.insert_resource(KeySound {
+        lane_keysound: [ObjId::null(); 16],
+    });
+    commands.insert_resource(HeldLanes::default());
+}
 
-    let wav_files = bms.notes.wav_files.clone();
+/// `.bme`/`.bml` charts and charts using key 6/7 channels are 7 key, anything else is 5 key.
+fn detect_key_mode(bms: &Bms, path: &Path, style: PlayStyle) -> KeyMode {
+    let seven_key_ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("bme") || ext.eq_ignore_ascii_case("bml"));
+    let uses_key_6_7 = bms.notes.all_notes().any(|wav_obj| {
+        lane_of_channel(wav_obj.channel_id.as_u16())
+            .and_then(|lane| lane.key_number())
+            .is_some_and(|n| n > 5)
+    });
+
+    match (style, seven_key_ext || uses_key_6_7) {
+        (PlayStyle::Double, true) => KeyMode::Beat14K,
+        (PlayStyle::Double, false) => KeyMode::Beat10K,
+        (_, true) => KeyMode::Beat7K,
+        (_, false) => KeyMode::Beat5K,
+    }
+}
+
+fn lane_of_channel(channel: u16) -> Option<Lane> {
+    CHANNEL_LANE_MAP
+        .iter()
+        .find(|(id, _)| *id == channel)
+        .map(|(_, lane)| *lane)
+}
+
+fn load_keysounds(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    lib: Res<BmsLib>,
+    bms_data: Res<BmsData>,
+) {
+    let wav_files = bms_data.data.notes.wav_files.clone();
 
     let mut audio_map = HashMap::new();
     for (id, pathbuf) in wav_files {
-        if let Some(file) = find_wav(
+        if let Some(file) = find_file(
             env::current_dir()
                 .unwrap()
                 .join(lib.cursor_dir().unwrap().join(&pathbuf).to_str().unwrap())
                 .to_str()
                 .unwrap(),
+            AUDIO_EXTS,
         ) {
             let handle: Handle<AudioSource> = asset_server.load(file);
             audio_map.insert(id, handle);
         }
     }
     commands.insert_resource(AudioAssets { map: audio_map });
+}
 
-    let bpm = bms.arrangers.bpm.clone().unwrap().to_f32().unwrap();
+fn spawn_notes(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    bms_data: Res<BmsData>,
+    layout: Res<LaneLayout>,
+    lane_query: Query<(Entity, &Lanes)>,
+) {
+    let bms = &bms_data.data;
+
+    let bpm = bms
+        .arrangers
+        .bpm
+        .as_ref()
+        .and_then(|bpm| bpm.to_f32())
+        .unwrap_or(DEFAULT_BPM);
     let green_number = 500;
 
     let mut section_len_changes_hashmap: HashMap<u64, f64> = HashMap::new();
@@ -595,23 +987,21 @@ fn spawn_notes(
     let blue_note_color = materials.add(Color::srgb(0., 0., 1.));
     let scratch_color = materials.add(Color::srgb(1., 0., 0.));
 
-    commands.insert_resource(PlayStatus {
-        green_number: green_number.clone(),
-        bpm: bpm.clone(),
-        start_time: 0.,
-    });
-
     for (_, bpm_change) in bpm_changes.into_iter().enumerate() {
         let real_crotchet = crotchet.get_crotchet(
             bpm_change.1.time.track.0,
             bpm_change.1.time.numerator as f64 / bpm_change.1.time.denominator as f64,
         );
-        commands.spawn((BPMEvent {
-            bpm: bpm_change.1.bpm.to_f32().unwrap(),
-            time: crotchet_crotchet_function.crotchet_time_function(real_crotchet) as f32,
-        },));
+        commands.spawn((
+            BPMEvent {
+                bpm: bpm_change.1.bpm.to_f32().unwrap(),
+                time: crotchet_crotchet_function.crotchet_time_function(real_crotchet) as f32,
+            },
+            OnGameplayScreen,
+        ));
     }
 
+    let mut last_time: f32 = 0.;
     let all_note = bms.notes.all_notes();
     for wav_obj in all_note {
         let real_crotchet = crotchet.get_crotchet(
@@ -619,307 +1009,427 @@ fn spawn_notes(
             wav_obj.offset.numerator as f64 / wav_obj.offset.denominator as f64,
         );
         let note_time = crotchet_crotchet_function.crotchet_time_function(real_crotchet) as f32;
+        last_time = last_time.max(note_time);
         let speed = LANE_HEIGHT / (green_number as f32 / 10. / 60.);
         let position_y = JUDGEMENTLINE_POSITION.y
             + 2.5 * crotchet_crotchet_function.distance(0., note_time as f64) as f32;
-        let lane = wav_obj.channel_id.as_u16();
-        if lane == 68 {
-            let lane_entity = lane_query
-                .iter()
-                .find(|(_, lane)| lane.0 == Lane::LS)
-                .map(|(e, _)| e)
-                .unwrap();
-            commands.entity(lane_entity).with_children(|parent| {
-                parent.spawn((
-                    Mesh2d(meshes.add(Rectangle::new(SCRATCH_WIDTH, SCRATCH_HEIGHT))),
-                    MeshMaterial2d(scratch_color.clone()),
-                    Transform::from_translation(
-                        Vec2::new(SCRATCH_L2R_RELATIVE_X, position_y).extend(0.),
-                    ),
-                    Note {
-                        time: note_time,
-                        wav_file: wav_obj.wav_id,
-                        real_crotchet: real_crotchet as f32,
-                    },
-                ));
-            });
-        } else if lane == 63 {
-            let lane_entity = lane_query
-                .iter()
-                .find(|(_, lane)| lane.0 == Lane::L1)
-                .map(|(e, _)| e)
-                .unwrap();
-            commands.entity(lane_entity).with_children(|parent| {
-                parent.spawn((
-                    Mesh2d(meshes.add(Rectangle::new(WHITE_NOTE_WIDTH, WHITE_NOTE_HEIGHT))),
-                    MeshMaterial2d(white_note_color.clone()),
-                    Transform::from_translation(
-                        Vec2::new(NOTE1_L2R_RELATIVE_X, position_y).extend(0.),
-                    ),
-                    Note {
-                        time: note_time,
-                        wav_file: wav_obj.wav_id,
-                        real_crotchet: real_crotchet as f32,
-                    },
-                ));
-            });
-        } else if lane == 64 {
-            let lane_entity = lane_query
-                .iter()
-                .find(|(_, lane)| lane.0 == Lane::L2)
-                .map(|(e, _)| e)
-                .unwrap();
-            commands.entity(lane_entity).with_children(|parent| {
-                parent.spawn((
-                    Mesh2d(meshes.add(Rectangle::new(BLUE_NOTE_WIDTH, BLUE_NOTE_HEIGHT))),
-                    MeshMaterial2d(blue_note_color.clone()),
-                    Transform::from_translation(
-                        Vec2::new(NOTE2_L2R_RELATIVE_X, position_y).extend(0.),
-                    ),
-                    Note {
-                        time: note_time,
-                        wav_file: wav_obj.wav_id,
-                        real_crotchet: real_crotchet as f32,
-                    },
-                ));
-            });
-        } else if lane == 65 {
-            let lane_entity = lane_query
-                .iter()
-                .find(|(_, lane)| lane.0 == Lane::L3)
-                .map(|(e, _)| e)
-                .unwrap();
-            commands.entity(lane_entity).with_children(|parent| {
-                parent.spawn((
-                    Mesh2d(meshes.add(Rectangle::new(WHITE_NOTE_WIDTH, WHITE_NOTE_HEIGHT))),
-                    MeshMaterial2d(white_note_color.clone()),
-                    Transform::from_translation(
-                        Vec2::new(NOTE3_L2R_RELATIVE_X, position_y).extend(0.),
-                    ),
-                    Note {
-                        time: note_time,
-                        wav_file: wav_obj.wav_id,
-                        real_crotchet: real_crotchet as f32,
-                    },
-                ));
-            });
-        } else if lane == 66 {
-            let lane_entity = lane_query
-                .iter()
-                .find(|(_, lane)| lane.0 == Lane::L4)
-                .map(|(e, _)| e)
-                .unwrap();
-            commands.entity(lane_entity).with_children(|parent| {
-                parent.spawn((
-                    Mesh2d(meshes.add(Rectangle::new(BLUE_NOTE_WIDTH, BLUE_NOTE_HEIGHT))),
-                    MeshMaterial2d(blue_note_color.clone()),
-                    Transform::from_translation(
-                        Vec2::new(NOTE4_L2R_RELATIVE_X, position_y).extend(0.),
-                    ),
-                    Note {
-                        time: note_time,
-                        wav_file: wav_obj.wav_id,
-                        real_crotchet: real_crotchet as f32,
-                    },
-                ));
-            });
-        } else if lane == 67 {
-            let lane_entity = lane_query
-                .iter()
-                .find(|(_, lane)| lane.0 == Lane::L5)
-                .map(|(e, _)| e)
-                .unwrap();
-            commands.entity(lane_entity).with_children(|parent| {
-                parent.spawn((
-                    Mesh2d(meshes.add(Rectangle::new(WHITE_NOTE_WIDTH, WHITE_NOTE_HEIGHT))),
-                    MeshMaterial2d(white_note_color.clone()),
-                    Transform::from_translation(
-                        Vec2::new(NOTE5_L2R_RELATIVE_X, position_y).extend(0.),
-                    ),
-                    Note {
-                        time: note_time,
-                        wav_file: wav_obj.wav_id,
-                        real_crotchet: real_crotchet as f32,
-                    },
-                ));
-            });
-        } else if lane == 70 {
-            let lane_entity = lane_query
-                .iter()
-                .find(|(_, lane)| lane.0 == Lane::L6)
-                .map(|(e, _)| e)
-                .unwrap();
-            commands.entity(lane_entity).with_children(|parent| {
-                parent.spawn((
-                    Mesh2d(meshes.add(Rectangle::new(BLUE_NOTE_WIDTH, BLUE_NOTE_HEIGHT))),
-                    MeshMaterial2d(blue_note_color.clone()),
-                    Transform::from_translation(
-                        Vec2::new(NOTE6_L2R_RELATIVE_X, position_y).extend(0.),
-                    ),
-                    Note {
-                        time: note_time,
-                        wav_file: wav_obj.wav_id,
-                        real_crotchet: real_crotchet as f32,
-                    },
-                ));
-            });
-        } else if lane == 71 {
+        let channel = wav_obj.channel_id.as_u16();
+        if let Some(lane) = lane_of_channel(channel) {
+            let Some(column) = layout.column(lane) else {
+                info!("lane not in layout: {:?}", lane);
+                continue;
+            };
+            let (height, color) = match lane.key_number() {
+                None => (SCRATCH_HEIGHT, scratch_color.clone()),
+                Some(n) if n % 2 == 0 => (BLUE_NOTE_HEIGHT, blue_note_color.clone()),
+                Some(_) => (WHITE_NOTE_HEIGHT, white_note_color.clone()),
+            };
             let lane_entity = lane_query
                 .iter()
-                .find(|(_, lane)| lane.0 == Lane::L7)
+                .find(|(_, lanes)| lanes.0 == lane)
                 .map(|(e, _)| e)
                 .unwrap();
             commands.entity(lane_entity).with_children(|parent| {
                 parent.spawn((
-                    Mesh2d(meshes.add(Rectangle::new(WHITE_NOTE_WIDTH, WHITE_NOTE_HEIGHT))),
-                    MeshMaterial2d(white_note_color.clone()),
-                    Transform::from_translation(
-                        Vec2::new(NOTE7_L2R_RELATIVE_X, position_y).extend(0.),
-                    ),
+                    Mesh2d(meshes.add(Rectangle::new(column.width, height))),
+                    MeshMaterial2d(color),
+                    Transform::from_translation(Vec2::new(column.x, position_y).extend(0.)),
                     Note {
+                        lane,
                         time: note_time,
                         wav_file: wav_obj.wav_id,
                         real_crotchet: real_crotchet as f32,
                     },
                 ));
             });
-        } else if lane == 1 {
+        } else if channel == BGM_CHANNEL {
             commands.spawn((
                 Transform::from_translation(Vec2::new(0., position_y).extend(0.)),
                 BGMEvent {
                     time: note_time,
                     wav_file: wav_obj.wav_id,
                 },
+                OnGameplayScreen,
             ));
         } else {
-            info!("not found: {}", lane);
+            info!("not found: {}", channel);
+        }
+    }
+
+    commands.insert_resource(PlayStatus {
+        green_number: green_number.clone(),
+        bpm: bpm.clone(),
+        end_time: last_time + CHART_END_TAIL,
+        finish_time: 0.,
+    });
+}
+
+/// Song time of `pos_in_measure`, a fraction of measure `track`.
+fn marker_time(calculator: &PositionCalculator, track: u64, pos_in_measure: f64) -> f32 {
+    let crotchet = calculator
+        .crotchet_calculator
+        .get_crotchet(track, pos_in_measure);
+    calculator.time_calculator.crotchet_time_function(crotchet) as f32
+}
+
+fn spawn_bar_lines(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    bms_data: Res<BmsData>,
+    layout: Res<LaneLayout>,
+    calculator: Res<PositionCalculator>,
+) {
+    let color = materials.add(Color::srgb(0.5, 0.5, 0.5));
+    let last_track = bms_data.data.last_obj_time().map_or(0, |time| time.track.0);
+    let markers = (0..=last_track + 1)
+        .map(|track| (marker_time(&calculator, track, 0.), color.clone(), None))
+        .collect();
+    spawn_lane_markers(&mut commands, &mut meshes, &layout, &calculator, markers);
+}
+
+/// BPM changes and stops, when the timing markers option is on.
+fn spawn_timing_markers(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    bms_data: Res<BmsData>,
+    layout: Res<LaneLayout>,
+    calculator: Res<PositionCalculator>,
+) {
+    let bms = &bms_data.data;
+    let bpm_marker_color = materials.add(Color::srgb(0., 1., 0.));
+    let stop_marker_color = materials.add(Color::srgb(1., 1., 0.));
+    let text_font = TextFont {
+        font: asset_server.load("fonts/KosugiMaru-Regular.ttf"),
+        font_size: 16.0,
+        ..default()
+    };
+    let label = |text: String| Some((Text2d::new(text), text_font.clone()));
+
+    let mut markers = vec![];
+    for bpm_change in bms.arrangers.bpm_changes.values() {
+        markers.push((
+            marker_time(
+                &calculator,
+                bpm_change.time.track.0,
+                bpm_change.time.numerator as f64 / bpm_change.time.denominator as f64,
+            ),
+            bpm_marker_color.clone(),
+            label(format!("BPM {}", bpm_change.bpm)),
+        ));
+    }
+    for stop in bms.arrangers.stops.values() {
+        markers.push((
+            marker_time(
+                &calculator,
+                stop.time.track.0,
+                stop.time.numerator as f64 / stop.time.denominator as f64,
+            ),
+            stop_marker_color.clone(),
+            label("STOP".to_string()),
+        ));
+    }
+    spawn_lane_markers(&mut commands, &mut meshes, &layout, &calculator, markers);
+}
+
+/// Song time, color and label of a lane marker.
+type LaneMarkerLine = (f32, Handle<ColorMaterial>, Option<(Text2d, TextFont)>);
+
+/// A line across each lane group at every marker's time, with its label to the right.
+fn spawn_lane_markers(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    layout: &LaneLayout,
+    calculator: &PositionCalculator,
+    markers: Vec<LaneMarkerLine>,
+) {
+    for (time, color, label) in markers {
+        let position_y = scroll_position_y(calculator, 0., time);
+        for group in &layout.groups {
+            let mut marker = commands.spawn((
+                Mesh2d(meshes.add(Rectangle::new(group.width, 1.))),
+                MeshMaterial2d(color.clone()),
+                Transform::from_translation(Vec3::new(group.center_x, position_y, -1.)),
+                LaneMarker { time },
+                OnGameplayScreen,
+            ));
+            if let Some(label) = &label {
+                marker.with_children(|parent| {
+                    parent.spawn((
+                        label.clone(),
+                        Transform::from_translation(Vec3::new(group.width / 2. + 8., 0., 0.)),
+                        Anchor::CENTER_LEFT,
+                    ));
+                });
+            }
         }
     }
 }
 
+/// The y of an object at song time `time` while the song is at `elapsed`.
+fn scroll_position_y(calculator: &PositionCalculator, elapsed: f64, time: f32) -> f32 {
+    let distance = calculator.time_calculator.distance(elapsed, time as f64) as f32;
+    if time as f64 <= elapsed {
+        JUDGEMENTLINE_POSITION.y - 2.5 * distance
+    } else {
+        JUDGEMENTLINE_POSITION.y + 2.5 * distance
+    }
+}
+
 fn notes_fall(
     mut commands: Commands,
-    time: Res<Time>,
+    clock: Res<SongClock>,
     mut query: Query<(Entity, &mut Transform, &mut Note)>,
     status: ResMut<PlayStatus>,
     calculator: ResMut<PositionCalculator>,
+    timing_window: Res<TimingWindow>,
+    mut judgements: MessageWriter<Judgement>,
 ) {
-    let current_time = time.elapsed_secs();
-    let elapsed = current_time - status.start_time;
+    // 音符按判定偏移后的时间下落
+    let scroll_time = clock.judge_time();
+    let elapsed = scroll_time as f32;
 
     let speed = LANE_HEIGHT / (status.green_number as f32 / 10. / 60.);
 
     for (entity, mut transform, note) in query.iter_mut() {
-        if note.time <= elapsed - TIMING_WINDOW.good {
-            commands.entity(entity).despawn()
-        }
-        let distance = calculator
-            .time_calculator
-            .distance(elapsed as f64, note.time as f64) as f32;
-        let new_pos = if note.time <= elapsed {
-            JUDGEMENTLINE_POSITION.y - 2.5 * distance
-        } else {
-            JUDGEMENTLINE_POSITION.y + 2.5 * distance
-        };
-        transform.translation.y = new_pos;
+        if note.time < elapsed - timing_window.bad {
+            commands.entity(entity).despawn();
+            judgements.write(Judgement {
+                lane: note.lane,
+                judge: Judge::Poor,
+                offset: 0.,
+            });
+            continue;
+        }
+        transform.translation.y = scroll_position_y(&calculator, scroll_time, note.time);
     }
 }
 
-fn play_bgm(
+fn lane_markers_fall(
     mut commands: Commands,
-    time: Res<Time>,
-    mut query: Query<(Entity, &mut BGMEvent)>,
-    audio_assets: Res<AudioAssets>,
-    audio: Res<Audio>,
-    status: ResMut<PlayStatus>,
+    clock: Res<SongClock>,
+    mut query: Query<(Entity, &mut Transform, &LaneMarker)>,
+    calculator: Res<PositionCalculator>,
 ) {
-    let current_time = time.elapsed_secs();
-    let elapsed = current_time - status.start_time;
+    let scroll_time = clock.judge_time();
 
-    for (entity, bgm_event) in query.iter_mut() {
-        if bgm_event.time <= elapsed {
+    for (entity, mut transform, marker) in query.iter_mut() {
+        if (marker.time as f64) < scroll_time {
             commands.entity(entity).despawn();
-            if let Some(handle) = audio_assets.map.get(&bgm_event.wav_file) {
-                audio.play(handle.clone());
-            }
+            continue;
         }
+        transform.translation.y = scroll_position_y(&calculator, scroll_time, marker.time);
     }
 }
 
+fn check_chart_end(
+    clock: Res<SongClock>,
+    status: Res<PlayStatus>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if clock.secs() > status.end_time {
+        next_state.set(AppState::Finished);
+    }
+}
+
+fn store_result(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut status: ResMut<PlayStatus>,
+    score: Res<score::Score>,
+    gauge: Res<gauge::Gauge>,
+    playback: Option<Res<ReplayPlayback>>,
+    autoplay: Option<Res<Autoplay>>,
+) {
+    status.finish_time = time.elapsed_secs();
+    commands.insert_resource(PlayResult {
+        gauge: gauge.kind,
+        cleared: gauge.is_cleared(),
+        notes: score.notes,
+        pgreat: score.pgreat,
+        great: score.great,
+        good: score.good,
+        bad: score.bad,
+        poor: score.poor,
+        empty_poor: score.empty_poor,
+        max_combo: score.max_combo,
+        fast: score.fast,
+        slow: score.slow,
+        offsets: score.offsets.clone(),
+        gauge_history: gauge.history.clone(),
+        watched: playback.is_some() || autoplay.is_some(),
+    });
+}
+
+fn show_result(
+    time: Res<Time>,
+    status: Res<PlayStatus>,
+    demo: Option<Res<DemoPlay>>,
+    mut next_screen: ResMut<NextState<Screen>>,
+) {
+    if time.elapsed_secs() - status.finish_time > RESULT_DELAY {
+        // 演示结束后直接回到选曲
+        if demo.is_some() {
+            next_screen.set(Screen::Select);
+        } else {
+            next_screen.set(Screen::Result);
+        }
+    }
+}
+
+fn cleanup_gameplay_screen(
+    mut commands: Commands,
+    query: Query<Entity, With<OnGameplayScreen>>,
+    audio: Res<Audio>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    for entity in &query {
+        commands.entity(entity).despawn();
+    }
+    audio.stop();
+    next_state.set(AppState::Loading);
+    commands.remove_resource::<ReplayPlayback>();
+    commands.remove_resource::<Autoplay>();
+    commands.remove_resource::<DemoPlay>();
+    // 下次进入时谱面读不了，ChartSetup 不能用上一首的数据
+    commands.remove_resource::<BmsData>();
+    commands.remove_resource::<LaneLayout>();
+}
+
 #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, States)]
 enum AppState {
     #[default]
     Loading,
     Playing,
+    /// The play has ended, e.g. a survival gauge emptied.
+    Finished,
 }
 
-const KEY_LANE_MAP: &[(KeyCode, Lane)] = &[
-    (KeyCode::KeyA, Lane::LS),
-    (KeyCode::KeyS, Lane::L1),
-    (KeyCode::KeyD, Lane::L2),
-    (KeyCode::KeyF, Lane::L3),
-    (KeyCode::Space, Lane::L4),
-    (KeyCode::KeyJ, Lane::L5),
-    (KeyCode::KeyK, Lane::L6),
-    (KeyCode::KeyL, Lane::L7),
-];
+/// A lane key going down or up.
+#[derive(Message, Debug, Clone, Copy)]
+struct LaneInput {
+    lane: Lane,
+    pressed: bool,
+    /// Song seconds of the frame that delivered the event, not of the key moving.
+    time: f32,
+}
 
+/// Quits to select, or starts the play once the first BGM chunk is mixed.
 fn keyboard_input(
-    mut commands: Commands,
-    audio_assets: Res<AudioAssets>,
-    audio: Res<Audio>,
-    keys: Res<ButtonInput<KeyCode>>,
-    time: Res<Time>,
+    input: BindingInput,
+    bindings: Res<KeyBindings>,
+    state: Res<State<AppState>>,
+    track: Option<Res<BgmTrack>>,
     mut next_state: ResMut<NextState<AppState>>,
-    mut status: ResMut<PlayStatus>,
-    lanes: Query<(&Lanes, &Children)>,
-    notes: Query<&Note>,
+    mut next_screen: ResMut<NextState<Screen>>,
 ) {
-    // Start
-    if keys.just_pressed(KeyCode::KeyY) {
+    if input.any_just_pressed(&bindings.select) {
+        next_screen.set(Screen::Select);
+    } else if *state.get() == AppState::Loading
+        && track.is_some()
+        && input.any_just_pressed(&bindings.start)
+    {
         next_state.set(AppState::Playing);
-        status.start_time = time.elapsed_secs();
+    }
+}
+
+/// Turns the frame's key and button events into lane inputs, each transition exactly once and
+/// in order. Every event of a frame gets the song time of that frame, winit and bevy_gilrs
+/// don't pass on when the event arrived.
+fn lane_input(
+    mut events: BindingEvents,
+    input: BindingInput,
+    bindings: Res<KeyBindings>,
+    layout: Res<LaneLayout>,
+    clock: Res<SongClock>,
+    state: Res<State<AppState>>,
+    mut inputs: MessageWriter<LaneInput>,
+) {
+    // 先读掉事件，开始前按下的键不会留到开始后
+    let events = events.read();
+    if *state.get() != AppState::Playing {
         return;
     }
 
-    let elapsed = time.elapsed_secs() - status.start_time;
+    let elapsed = clock.judge_secs();
 
-    // 遍历按键映射
-    for (key, target_lane) in KEY_LANE_MAP {
-        if keys.just_pressed(*key) {
-            let (_, children) = lanes
-                .iter()
-                .find(|(lane, _)| lane.0 == *target_lane)
-                .unwrap();
+    let lane_keys = layout.lane_keys(&bindings);
+    for (binding, pressed) in events {
+        let Some((lane, keys)) = lane_keys.iter().find(|(_, keys)| keys.contains(&binding)) else {
+            continue;
+        };
+        // 同一 lane 的多个键都松开才算松开
+        if !pressed && input.any_pressed(keys) {
+            continue;
+        }
+        inputs.write(LaneInput {
+            lane: *lane,
+            pressed,
+            time: elapsed,
+        });
+    }
+}
 
-            let closest = children
-                .iter()
-                .filter_map(|child| notes.get(child).ok().map(|note| (child, note)))
-                .min_by(|(_, a_note), (_, b_note)| {
-                    let da = (a_note.time - elapsed).abs();
-                    let db = (b_note.time - elapsed).abs();
-                    da.total_cmp(&db)
-                });
+/// Judges lane presses from the keyboard or a replay against the closest note in the lane.
+fn judge_input(
+    mut commands: Commands,
+    lanes: Query<(&Lanes, &Children)>,
+    notes: Query<&Note>,
+    timing_window: Res<TimingWindow>,
+    keysounds: Keysounds,
+    mut inputs: MessageReader<LaneInput>,
+    mut judgements: MessageWriter<Judgement>,
+) {
+    // 同一帧内已经打掉的音符，despawn 要到帧末才生效
+    let mut consumed = vec![];
+    for input in inputs.read().filter(|input| input.pressed) {
+        let (_, children) = lanes.iter().find(|(lane, _)| lane.0 == input.lane).unwrap();
+
+        let lane_notes = children
+            .iter()
+            .filter(|child| !consumed.contains(child))
+            .filter_map(|child| notes.get(child).ok().map(|note| (child, note.time)));
+        let Some((entity, judge, offset)) = timing_window.judge_press(lane_notes, input.time)
+        else {
+            // 判定范围内没有音符时播放该 lane 当前的按键音
+            keysounds.play_lane(input.lane);
+            continue;
+        };
 
-            if let Some((entity, note)) = closest {
-                if note.time - TIMING_WINDOW.good <= elapsed
-                    && elapsed <= note.time + TIMING_WINDOW.good
-                {
-                    if let Some(handle) = audio_assets.map.get(&note.wav_file) {
-                        audio.play(handle.clone());
-                    }
-                    commands.entity(entity).despawn();
-                }
-            }
+        keysounds.play_note(notes.get(entity).unwrap());
+        if judge.consumes_note() {
+            commands.entity(entity).despawn();
+            consumed.push(entity);
         }
+        judgements.write(Judgement {
+            lane: input.lane,
+            judge,
+            offset,
+        });
+    }
+}
+
+fn update_held_lanes(mut held: ResMut<HeldLanes>, mut inputs: MessageReader<LaneInput>) {
+    for input in inputs.read() {
+        held.0[input.lane as usize] = input.pressed;
     }
 }
 
-// fn update_keysound(
-//     mut key_sound: ResMut<KeySound>,
-//     time: Res<Time>,
-//     mut query: Query<(Entity, &mut BGMEvent)>,
-// ) {
-//     let current_time = time.elapsed_secs();
-//     let elapsed = current_time - status.start_time;
-// }
+/// Points each lane's keysound at its next note, keeping the last one once the lane is done.
+fn update_keysound(
+    mut key_sound: ResMut<KeySound>,
+    lanes: Query<(&Lanes, &Children)>,
+    notes: Query<&Note>,
+) {
+    for (lane, children) in &lanes {
+        let next = children
+            .iter()
+            .filter_map(|child| notes.get(child).ok())
+            .min_by(|a, b| a.time.total_cmp(&b.time));
+        if let Some(note) = next {
+            key_sound.lane_keysound[lane.0 as usize] = note.wav_file;
+        }
+    }
+}
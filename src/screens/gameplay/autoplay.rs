@@ -0,0 +1,88 @@
+use bevy::prelude::*;
+
+use super::{AppState, BgmTrack, Lane, LaneInput, Note, SongClock, judge_input};
+use crate::resources::{DemoPlay, PlayOptions};
+use crate::screens::Screen;
+
+/// How long autoplay holds a key down after pressing it.
+const AUTOPLAY_HOLD: f32 = 0.08;
+
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(OnEnter(Screen::Gameplay), enable_autoplay)
+        .add_systems(
+            Update,
+            (
+                start_demo.run_if(
+                    in_state(AppState::Loading)
+                        .and(resource_exists::<DemoPlay>)
+                        .and(resource_exists::<BgmTrack>),
+                ),
+                autoplay
+                    .before(judge_input)
+                    .run_if(in_state(AppState::Playing).and(resource_exists::<Autoplay>)),
+            )
+                .run_if(in_state(Screen::Gameplay)),
+        )
+        .add_systems(
+            Update,
+            leave_demo.run_if(in_state(Screen::Gameplay).and(resource_exists::<DemoPlay>)),
+        );
+}
+
+/// Present while notes are hit by autoplay instead of the keyboard.
+#[derive(Resource, Default)]
+pub(super) struct Autoplay {
+    /// Lanes pressed by autoplay and the song time to release each at.
+    releases: Vec<(Lane, f32)>,
+}
+
+fn enable_autoplay(mut commands: Commands, options: Res<PlayOptions>, demo: Option<Res<DemoPlay>>) {
+    if options.autoplay || demo.is_some() {
+        commands.insert_resource(Autoplay::default());
+    }
+}
+
+/// Starts the demo once the first BGM chunk is mixed, there is no one to press start.
+fn start_demo(mut next_state: ResMut<NextState<AppState>>) {
+    next_state.set(AppState::Playing);
+}
+
+/// Presses each note at its exact time and releases the key shortly after.
+fn autoplay(
+    clock: Res<SongClock>,
+    notes: Query<&Note>,
+    mut autoplay: ResMut<Autoplay>,
+    mut inputs: MessageWriter<LaneInput>,
+) {
+    let elapsed = clock.judge_secs();
+
+    autoplay.releases.retain(|(lane, time)| {
+        if *time > elapsed {
+            return true;
+        }
+        inputs.write(LaneInput {
+            lane: *lane,
+            pressed: false,
+            time: *time,
+        });
+        false
+    });
+
+    for note in notes.iter().filter(|note| note.time <= elapsed) {
+        inputs.write(LaneInput {
+            lane: note.lane,
+            pressed: true,
+            time: note.time,
+        });
+        autoplay
+            .releases
+            .push((note.lane, note.time + AUTOPLAY_HOLD));
+    }
+}
+
+/// Any key ends the demo and goes back to select.
+fn leave_demo(keys: Res<ButtonInput<KeyCode>>, mut next_screen: ResMut<NextState<Screen>>) {
+    if keys.get_just_pressed().next().is_some() {
+        next_screen.set(Screen::Select);
+    }
+}
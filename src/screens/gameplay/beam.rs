@@ -0,0 +1,202 @@
+use bevy::prelude::*;
+
+use super::judgement::{Judge, Judgement};
+use super::{
+    AppState, ChartSetup, HeldLanes, JUDGEMENTLINE_POSITION, LANE_HEIGHT, Lane, LaneLayout,
+    OnGameplayScreen,
+};
+use crate::screens::Screen;
+
+const BEAM_HEIGHT: f32 = LANE_HEIGHT * 0.4;
+const BEAM_ALPHA: f32 = 0.35;
+/// Seconds a beam takes to fade out after the lane is released.
+const BEAM_FADE: f32 = 0.12;
+
+/// White keys under the gauge, blue keys raised by `BLUE_KEY_RAISE` like on a controller.
+const KEY_POSITION_Y: f32 = 1080. / 2. - LANE_HEIGHT - 130.;
+const KEY_HEIGHT: f32 = 50.;
+const BLUE_KEY_RAISE: f32 = 30.;
+
+const BOMB_DURATION: f32 = 0.25;
+const BOMB_RADIUS: f32 = 40.;
+
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(
+        OnEnter(Screen::Gameplay),
+        (spawn_beams, spawn_keys).in_set(ChartSetup),
+    )
+    .add_systems(
+        Update,
+        (update_beams, update_keys, spawn_bombs, update_bombs).run_if(in_state(AppState::Playing)),
+    );
+}
+
+/// Lights up the lane column while the lane is held, fading out after release.
+#[derive(Component)]
+struct KeyBeam {
+    lane: Lane,
+    /// 1 while held, falling to 0 over `BEAM_FADE` after release.
+    strength: f32,
+}
+
+#[derive(Component)]
+struct KeyGraphic {
+    lane: Lane,
+}
+
+/// Hit explosion at the judgement line, growing and fading out over `BOMB_DURATION`.
+#[derive(Component)]
+struct Bomb {
+    timer: Timer,
+}
+
+/// Same palette as the notes: red scratch, blue even keys, white odd keys.
+fn lane_color(lane: Lane) -> Color {
+    match lane.key_number() {
+        None => Color::srgb(1., 0., 0.),
+        Some(n) if n % 2 == 0 => Color::srgb(0., 0., 1.),
+        Some(_) => Color::srgb(1., 1., 1.),
+    }
+}
+
+fn spawn_beams(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    layout: Res<LaneLayout>,
+) {
+    for column in &layout.columns {
+        // 光柱从判定线向上延伸
+        let y = JUDGEMENTLINE_POSITION.y + BEAM_HEIGHT / 2.;
+        commands.spawn((
+            Mesh2d(meshes.add(Rectangle::new(column.width, BEAM_HEIGHT))),
+            MeshMaterial2d(materials.add(lane_color(column.lane).with_alpha(0.))),
+            Transform::from_translation(Vec3::new(column.x, y, -1.)),
+            KeyBeam {
+                lane: column.lane,
+                strength: 0.,
+            },
+            OnGameplayScreen,
+        ));
+    }
+}
+
+fn spawn_keys(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    layout: Res<LaneLayout>,
+) {
+    for column in &layout.columns {
+        let (mesh, y) = match column.lane.key_number() {
+            None => (meshes.add(Circle::new(column.width / 2.)), KEY_POSITION_Y),
+            Some(n) if n % 2 == 0 => (
+                meshes.add(Rectangle::new(column.width, KEY_HEIGHT)),
+                KEY_POSITION_Y + BLUE_KEY_RAISE,
+            ),
+            Some(_) => (
+                meshes.add(Rectangle::new(column.width, KEY_HEIGHT)),
+                KEY_POSITION_Y,
+            ),
+        };
+        commands.spawn((
+            Mesh2d(mesh),
+            MeshMaterial2d(materials.add(key_color(column.lane, false))),
+            Transform::from_translation(Vec3::new(column.x, y, 0.)),
+            KeyGraphic { lane: column.lane },
+            OnGameplayScreen,
+        ));
+    }
+}
+
+fn key_color(lane: Lane, pressed: bool) -> Color {
+    let color = lane_color(lane);
+    if pressed { color } else { color.darker(0.6) }
+}
+
+fn update_beams(
+    time: Res<Time>,
+    held: Res<HeldLanes>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut beams: Query<(&mut KeyBeam, &MeshMaterial2d<ColorMaterial>)>,
+) {
+    for (mut beam, material) in &mut beams {
+        let strength = if held.0[beam.lane as usize] {
+            1.
+        } else {
+            (beam.strength - time.delta_secs() / BEAM_FADE).max(0.)
+        };
+        if strength == beam.strength {
+            continue;
+        }
+        beam.strength = strength;
+        if let Some(material) = materials.get_mut(&material.0) {
+            material.color.set_alpha(BEAM_ALPHA * strength);
+        }
+    }
+}
+
+fn update_keys(
+    held: Res<HeldLanes>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    keys: Query<(&KeyGraphic, &MeshMaterial2d<ColorMaterial>)>,
+) {
+    if !held.is_changed() {
+        return;
+    }
+    for (key, material) in &keys {
+        if let Some(material) = materials.get_mut(&material.0) {
+            material.color = key_color(key.lane, held.0[key.lane as usize]);
+        }
+    }
+}
+
+fn spawn_bombs(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    layout: Res<LaneLayout>,
+    mut judgements: MessageReader<Judgement>,
+) {
+    for judgement in judgements.read() {
+        if !matches!(judgement.judge, Judge::PGreat | Judge::Great) {
+            continue;
+        }
+        let Some(column) = layout.column(judgement.lane) else {
+            continue;
+        };
+        commands.spawn((
+            Mesh2d(meshes.add(Circle::new(BOMB_RADIUS))),
+            MeshMaterial2d(materials.add(judgement.judge.color())),
+            Transform::from_translation(Vec3::new(column.x, JUDGEMENTLINE_POSITION.y, 1.)),
+            Bomb {
+                timer: Timer::from_seconds(BOMB_DURATION, TimerMode::Once),
+            },
+            OnGameplayScreen,
+        ));
+    }
+}
+
+fn update_bombs(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut bombs: Query<(
+        Entity,
+        &mut Bomb,
+        &mut Transform,
+        &MeshMaterial2d<ColorMaterial>,
+    )>,
+) {
+    for (entity, mut bomb, mut transform, material) in &mut bombs {
+        if bomb.timer.tick(time.delta()).is_finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        let progress = bomb.timer.fraction();
+        transform.scale = Vec3::splat(0.5 + progress);
+        if let Some(material) = materials.get_mut(&material.0) {
+            material.color.set_alpha(1. - progress);
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use std::env;
+
+use bevy::platform::collections::{HashMap, HashSet};
+use bevy::prelude::*;
+use bevy::render::render_resource::TextureFormat;
+use bms_rs::bms::model::obj::BgaLayer;
+use bms_rs::command::{ObjId, PoorMode};
+
+use super::{
+    AppState, BmsData, ChartSetup, IMAGE_EXTS, Judge, Judgement, LANE_GROUP_GAP, LaneLayout,
+    OnGameplayScreen, PlayStyle, PositionCalculator, SongClock, find_file,
+};
+use crate::resources::BmsLib;
+use crate::screens::Screen;
+
+const BGA_SIZE: f32 = 512.;
+const BGA_MARGIN: f32 = 40.;
+const POOR_BGA_DURATION: f32 = 1.;
+
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(
+        OnEnter(Screen::Gameplay),
+        spawn_bga.after(super::spawn_notes).in_set(ChartSetup),
+    )
+    .add_systems(Update, key_out_black.run_if(in_state(Screen::Gameplay)))
+    .add_systems(Update, update_bga.run_if(in_state(AppState::Playing)));
+}
+
+#[derive(Resource)]
+struct BgaAssets {
+    map: HashMap<ObjId, Handle<Image>>,
+    /// Images placed on a layer channel, whose black pixels are keyed out once loaded.
+    transparent: HashSet<AssetId<Image>>,
+}
+
+#[derive(Resource)]
+struct PoorBga {
+    mode: PoorMode,
+    until: f32,
+}
+
+#[derive(Component)]
+struct BgaSprite {
+    layer: BgaLayer,
+    has_image: bool,
+}
+
+#[derive(Component)]
+struct BgaEvent {
+    time: f32,
+    layer: BgaLayer,
+    bmp: ObjId,
+}
+
+fn spawn_bga(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    lib: Res<BmsLib>,
+    bms_data: Res<BmsData>,
+    layout: Res<LaneLayout>,
+    calculator: Res<PositionCalculator>,
+) {
+    let graphics = &bms_data.data.graphics;
+
+    let mut map = HashMap::new();
+    for (id, bmp) in &graphics.bmp_files {
+        if let Some(file) = find_file(
+            env::current_dir()
+                .unwrap()
+                .join(lib.cursor_dir().unwrap().join(&bmp.file))
+                .to_str()
+                .unwrap(),
+            IMAGE_EXTS,
+        ) {
+            let handle: Handle<Image> = asset_server.load(file);
+            map.insert(*id, handle);
+        }
+    }
+
+    let transparent = graphics
+        .bga_changes
+        .values()
+        .filter(|bga_obj| bga_obj.layer != BgaLayer::Base)
+        .filter_map(|bga_obj| map.get(&bga_obj.id))
+        .map(|handle| handle.id())
+        .collect();
+
+    for bga_obj in graphics.bga_changes.values() {
+        let real_crotchet = calculator.crotchet_calculator.get_crotchet(
+            bga_obj.time.track.0,
+            bga_obj.time.numerator as f64 / bga_obj.time.denominator as f64,
+        );
+        commands.spawn((
+            BgaEvent {
+                time: calculator
+                    .time_calculator
+                    .crotchet_time_function(real_crotchet) as f32,
+                layer: bga_obj.layer,
+                bmp: bga_obj.id,
+            },
+            OnGameplayScreen,
+        ));
+    }
+
+    let (center_x, size) = match layout.style {
+        PlayStyle::Single1P => {
+            let group = &layout.groups[0];
+            (
+                group.center_x + group.width / 2. + BGA_MARGIN + BGA_SIZE / 2.,
+                BGA_SIZE,
+            )
+        }
+        PlayStyle::Single2P => {
+            let group = &layout.groups[0];
+            (
+                group.center_x - group.width / 2. - BGA_MARGIN - BGA_SIZE / 2.,
+                BGA_SIZE,
+            )
+        }
+        PlayStyle::Double => (0., LANE_GROUP_GAP - BGA_MARGIN / 2.),
+    };
+
+    // #BMP00 is shown on POOR until the chart places its own POOR image.
+    let default_poor = ObjId::try_from("00")
+        .ok()
+        .and_then(|id| map.get(&id).cloned());
+
+    for (z, layer) in [
+        BgaLayer::Base,
+        BgaLayer::Overlay,
+        BgaLayer::Overlay2,
+        BgaLayer::Poor,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let image = match layer {
+            BgaLayer::Poor => default_poor.clone(),
+            _ => None,
+        };
+        commands.spawn((
+            Sprite {
+                image: image.clone().unwrap_or_default(),
+                custom_size: Some(Vec2::splat(size)),
+                ..default()
+            },
+            Transform::from_translation(Vec3::new(center_x, super::BORDER_CENTER_Y, z as f32)),
+            Visibility::Hidden,
+            BgaSprite {
+                layer,
+                has_image: image.is_some(),
+            },
+            OnGameplayScreen,
+        ));
+    }
+
+    commands.insert_resource(BgaAssets { map, transparent });
+    commands.insert_resource(PoorBga {
+        mode: graphics.poor_bga_mode,
+        until: f32::NEG_INFINITY,
+    });
+}
+
+fn key_out_black(
+    mut events: MessageReader<AssetEvent<Image>>,
+    mut images: ResMut<Assets<Image>>,
+    bga_assets: Option<Res<BgaAssets>>,
+) {
+    let Some(bga_assets) = bga_assets else {
+        return;
+    };
+
+    for event in events.read() {
+        if let AssetEvent::LoadedWithDependencies { id } = event {
+            if !bga_assets.transparent.contains(id) {
+                continue;
+            }
+            if let Some(image) = images.get_mut(*id) {
+                if let Some(converted) = image.convert(TextureFormat::Rgba8UnormSrgb) {
+                    *image = converted;
+                }
+                if let Some(data) = image.data.as_mut() {
+                    for pixel in data.chunks_exact_mut(4) {
+                        if pixel[..3] == [0, 0, 0] {
+                            pixel[3] = 0;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn update_bga(
+    mut commands: Commands,
+    clock: Res<SongClock>,
+    bga_assets: Res<BgaAssets>,
+    mut poor_bga: ResMut<PoorBga>,
+    mut judgements: MessageReader<Judgement>,
+    events: Query<(Entity, &BgaEvent)>,
+    mut sprites: Query<(&mut BgaSprite, &mut Sprite, &mut Visibility)>,
+) {
+    let elapsed = clock.secs();
+
+    for (entity, bga_event) in events.iter() {
+        if bga_event.time <= elapsed {
+            commands.entity(entity).despawn();
+            let Some(handle) = bga_assets.map.get(&bga_event.bmp) else {
+                continue;
+            };
+            for (mut bga_sprite, mut sprite, _) in sprites.iter_mut() {
+                if bga_sprite.layer == bga_event.layer {
+                    sprite.image = handle.clone();
+                    bga_sprite.has_image = true;
+                }
+            }
+        }
+    }
+
+    let poor_count = judgements
+        .read()
+        .filter(|judgement| matches!(judgement.judge, Judge::Poor | Judge::EmptyPoor))
+        .count();
+    if poor_count > 0 {
+        poor_bga.until = elapsed + POOR_BGA_DURATION;
+    }
+    let poor_active = elapsed < poor_bga.until && poor_bga.mode != PoorMode::Hidden;
+
+    for (bga_sprite, _, mut visibility) in sprites.iter_mut() {
+        let visible = match bga_sprite.layer {
+            BgaLayer::Poor => poor_active,
+            _ => !(poor_active && poor_bga.mode == PoorMode::Interrupt),
+        };
+        *visibility = if visible && bga_sprite.has_image {
+            Visibility::Visible
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
@@ -0,0 +1,394 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bevy::asset::LoadState;
+use bevy::prelude::*;
+use bevy::tasks::{AsyncComputeTaskPool, Task, futures::check_ready};
+use bevy_kira_audio::prelude::*;
+use kira::StartTime;
+use kira::sound::PlaybackPosition;
+
+use super::{AppState, AudioAssets, Autoplay, BGMEvent, Note, PlayStatus};
+use crate::screens::Screen;
+
+/// Sample rate the BGM chunks are mixed at.
+const TRACK_SAMPLE_RATE: u32 = 44100;
+/// Song seconds each BGM chunk holds the sounds of.
+const CHUNK_SECS: f32 = 4.;
+/// How long before its start a BGM chunk is handed to the audio thread.
+const QUEUE_AHEAD_SECS: f64 = 1.;
+/// Frames mixed and limited at a time.
+const MIX_BLOCK_FRAMES: usize = 1024;
+/// Peak level the limiter keeps the mix under, a little below full scale.
+const LIMITER_CEILING: f32 = 0.9;
+/// Fraction of the way back to unity gain the limiter recovers each block.
+const LIMITER_RELEASE: f32 = 0.02;
+
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(
+        Update,
+        (
+            (
+                mix_bgm_track.run_if(
+                    not(resource_exists::<BgmMixing>)
+                        .and(not(resource_exists::<BgmTrack>))
+                        .and(resource_exists::<AudioAssets>)
+                        .and(keysounds_loaded),
+                ),
+                finish_bgm_track.run_if(resource_exists::<BgmMixing>),
+            )
+                .run_if(in_state(AppState::Loading)),
+            queue_bgm_chunks.run_if(in_state(AppState::Playing).and(resource_exists::<BgmTrack>)),
+        )
+            .run_if(in_state(Screen::Gameplay)),
+    )
+    .add_systems(OnExit(Screen::Gameplay), remove_bgm_track);
+}
+
+/// The chart's BGM, and the keysounds of autoplay, mixed a chunk at a time while the song
+/// plays. Their playback position is the song clock.
+///
+/// bevy_kira_audio only plays static sounds and keeps kira's clocks to itself, so each chunk of
+/// `CHUNK_SECS` is mixed ahead into one sound, with every event on its exact sample, and queued
+/// with a start delay read from the chunk playing. A chunk keeps the sounds starting in it whole
+/// and overlaps the next one by their tails, so the seam, which lands within an audio block of
+/// its song time, never cuts a sound. Only the first chunk is mixed before the play can start,
+/// and no more than a few are alive at a time, whatever the chart's length.
+#[derive(Resource)]
+pub(super) struct BgmTrack {
+    sounds: Arc<[(f32, StaticSoundData)]>,
+    chunk_count: usize,
+    /// The chunk to queue next.
+    next: usize,
+    mixing: Option<Task<AudioSource>>,
+    /// The mix of `next`, once done.
+    mixed: Option<AudioSource>,
+    queued: Vec<QueuedChunk>,
+}
+
+impl BgmTrack {
+    /// Song seconds the audio thread has played up to, read from the latest chunk that has
+    /// started.
+    pub(super) fn position(&self, instances: &Assets<AudioInstance>) -> Option<f64> {
+        self.queued.iter().rev().find_map(|chunk| {
+            let position = instances.get(&chunk.instance)?.state().position()?;
+            (position > 0.).then_some(chunk.start + position)
+        })
+    }
+}
+
+/// A chunk handed to the audio thread, playing or waiting for its start.
+struct QueuedChunk {
+    /// Song seconds the chunk starts at.
+    start: f64,
+    /// Held so the mix stays loaded until the chunk has played.
+    _source: Handle<AudioSource>,
+    instance: Handle<AudioInstance>,
+}
+
+/// The first chunk, mixing while the play loads.
+#[derive(Resource)]
+struct BgmMixing {
+    sounds: Arc<[(f32, StaticSoundData)]>,
+    chunk_count: usize,
+    task: Task<AudioSource>,
+}
+
+/// Every keysound has loaded or failed to.
+fn keysounds_loaded(asset_server: Res<AssetServer>, audio_assets: Res<AudioAssets>) -> bool {
+    !audio_assets.map.values().any(|handle| {
+        matches!(
+            asset_server.get_load_state(handle.id()),
+            Some(LoadState::NotLoaded | LoadState::Loading)
+        )
+    })
+}
+
+/// The chunk a sound at `time` starts in.
+fn chunk_of(time: f32) -> usize {
+    (time.max(0.) / CHUNK_SECS) as usize
+}
+
+fn mix_bgm_track(
+    mut commands: Commands,
+    audio_assets: Res<AudioAssets>,
+    sources: Res<Assets<AudioSource>>,
+    status: Res<PlayStatus>,
+    autoplay: Option<Res<Autoplay>>,
+    bgm_events: Query<&BGMEvent>,
+    notes: Query<&Note>,
+) {
+    let mut events: Vec<_> = bgm_events
+        .iter()
+        .map(|event| (event.time, event.wav_file))
+        .collect();
+    // 自动演奏的按键音也提前混进去
+    if autoplay.is_some() {
+        events.extend(notes.iter().map(|note| (note.time, note.wav_file)));
+    }
+    let sounds: Arc<[_]> = events
+        .into_iter()
+        .filter_map(|(time, wav_file)| {
+            let source = sources.get(audio_assets.map.get(&wav_file)?)?;
+            Some((time, source.sound.clone()))
+        })
+        .collect();
+
+    // 最后一块至少到谱面结束，让歌曲时钟一直走到结尾
+    let last = sounds
+        .iter()
+        .map(|(time, _)| *time)
+        .fold(status.end_time, f32::max);
+    let chunk_count = chunk_of(last) + 1;
+    let task = AsyncComputeTaskPool::get().spawn({
+        let sounds = sounds.clone();
+        async move { mix_chunk(&sounds, 0) }
+    });
+    commands.insert_resource(BgmMixing {
+        sounds,
+        chunk_count,
+        task,
+    });
+}
+
+fn finish_bgm_track(mut commands: Commands, mut mixing: ResMut<BgmMixing>) {
+    if let Some(chunk) = check_ready(&mut mixing.task) {
+        commands.insert_resource(BgmTrack {
+            sounds: mixing.sounds.clone(),
+            chunk_count: mixing.chunk_count,
+            next: 0,
+            mixing: None,
+            mixed: Some(chunk),
+            queued: vec![],
+        });
+        commands.remove_resource::<BgmMixing>();
+    }
+}
+
+/// Queues the next chunk once the song is within `QUEUE_AHEAD_SECS` of it, starts mixing the
+/// one after, and frees the chunks that have played.
+fn queue_bgm_chunks(
+    audio: Res<Audio>,
+    mut sources: ResMut<Assets<AudioSource>>,
+    instances: Res<Assets<AudioInstance>>,
+    mut track: ResMut<BgmTrack>,
+) {
+    let track = &mut *track;
+    track.queued.retain(|chunk| {
+        !instances
+            .get(&chunk.instance)
+            .is_some_and(|instance| matches!(instance.state(), PlaybackState::Stopped))
+    });
+    if let Some(task) = &mut track.mixing
+        && let Some(chunk) = check_ready(task)
+    {
+        track.mixed = Some(chunk);
+        track.mixing = None;
+    }
+
+    let start = track.next as f64 * CHUNK_SECS as f64;
+    // 读到的位置是音频线程上一次处理到的地方，也是这一帧的命令开始生效的地方
+    let delay = start - track.position(&instances).unwrap_or(0.);
+    if delay > QUEUE_AHEAD_SECS {
+        return;
+    }
+    let Some(mut chunk) = track.mixed.take() else {
+        return;
+    };
+    let settings = &mut chunk.sound.settings;
+    if delay > 0. {
+        settings.start_time = StartTime::Delayed(Duration::from_secs_f64(delay));
+    } else {
+        // 没来得及混好就从现在的位置开始放
+        settings.start_position = PlaybackPosition::Seconds(-delay);
+    }
+    let source = sources.add(chunk);
+    let instance = audio.play(source.clone()).handle();
+    track.queued.push(QueuedChunk {
+        start,
+        _source: source,
+        instance,
+    });
+
+    track.next += 1;
+    if track.next < track.chunk_count {
+        let (sounds, index) = (track.sounds.clone(), track.next);
+        track.mixing =
+            Some(AsyncComputeTaskPool::get().spawn(async move { mix_chunk(&sounds, index) }));
+    }
+}
+
+/// A sound resampled to `TRACK_SAMPLE_RATE`: its first frame in the chunk, the source frames
+/// per chunk frame and its length in chunk frames.
+type Placed<'a> = (i64, f64, i64, &'a StaticSoundData);
+
+/// Places a sound of the chart on the chunk starting at `chunk_start` song seconds.
+fn place((time, sound): &(f32, StaticSoundData), chunk_start: f64) -> Placed<'_> {
+    let rate = TRACK_SAMPLE_RATE as f64;
+    let first = ((time.max(0.) as f64 - chunk_start) * rate).round() as i64;
+    let step = sound.sample_rate as f64 / rate;
+    let frames = (sound.num_frames() as f64 / step).ceil() as i64;
+    (first, step, frames, sound)
+}
+
+/// Mixes the sounds starting in chunk `index` in whole, each at its song time and resampled to
+/// `TRACK_SAMPLE_RATE`, a block at a time. The chunk lasts at least `CHUNK_SECS`, so one is
+/// always playing to keep the song clock running until the chart ends.
+fn mix_chunk(sounds: &[(f32, StaticSoundData)], index: usize) -> AudioSource {
+    let rate = TRACK_SAMPLE_RATE as f64;
+    let chunk_start = index as f64 * CHUNK_SECS as f64;
+    let (own, others): (Vec<_>, Vec<_>) = sounds
+        .iter()
+        .partition(|(time, _)| chunk_of(*time) == index);
+    let own: Vec<_> = own
+        .into_iter()
+        .map(|sound| place(sound, chunk_start))
+        .collect();
+    let total = own
+        .iter()
+        .map(|(first, _, frames, _)| first + frames)
+        .fold((CHUNK_SECS as f64 * rate).ceil() as i64, i64::max);
+    // 别的块里同时在响的声音只算进峰值，让重叠的部分一起压下去
+    let others: Vec<_> = others
+        .into_iter()
+        .map(|sound| place(sound, chunk_start))
+        .filter(|(first, _, frames, _)| first + frames > 0 && *first < total)
+        .collect();
+
+    // 直接分配在 Arc 里，交给音频线程时不再复制
+    let mut frames: Arc<[Frame]> = (0..total).map(|_| Frame::ZERO).collect();
+    let out = Arc::get_mut(&mut frames).unwrap();
+
+    let mut rest = [Frame::ZERO; MIX_BLOCK_FRAMES];
+    let mut peaks = vec![];
+    for (index, block) in out.chunks_mut(MIX_BLOCK_FRAMES).enumerate() {
+        let block_start = (index * MIX_BLOCK_FRAMES) as i64;
+        add_sounds(block, block_start, &own);
+        let rest = &mut rest[..block.len()];
+        rest.fill(Frame::ZERO);
+        add_sounds(rest, block_start, &others);
+        let peak = block
+            .iter()
+            .zip(rest.iter())
+            .map(|(&own, &other)| own + other)
+            .map(|frame| frame.left.abs().max(frame.right.abs()))
+            .fold(0., f32::max);
+        peaks.push(peak);
+    }
+    limit(out, &peaks);
+
+    AudioSource {
+        sound: StaticSoundData {
+            sample_rate: TRACK_SAMPLE_RATE,
+            frames,
+            settings: StaticSoundSettings::default(),
+            slice: None,
+        },
+    }
+}
+
+/// Adds the part of each sound falling in `block`, which begins `block_start` frames into the
+/// chunk.
+fn add_sounds(block: &mut [Frame], block_start: i64, sounds: &[Placed]) {
+    let block_end = block_start + block.len() as i64;
+    for &(first, step, count, sound) in sounds {
+        for i in first.max(block_start)..(first + count).min(block_end) {
+            // 线性插值重采样
+            let position = (i - first) as f64 * step;
+            let index = position as usize;
+            let a = sound.frame_at_index(index).unwrap_or_default();
+            let b = sound.frame_at_index(index + 1).unwrap_or_default();
+            block[(i - block_start) as usize] += a + (b - a) * position.fract() as f32;
+        }
+    }
+}
+
+/// Turns the mix down where sounds pile up past `LIMITER_CEILING`, given the peak of each
+/// block. The gain at each block boundary is no more than either neighbouring block allows and
+/// ramps linearly in between, so it is already down when a loud block starts, then recovers by
+/// `LIMITER_RELEASE` a block.
+fn limit(frames: &mut [Frame], peaks: &[f32]) {
+    let allowed: Vec<f32> = peaks
+        .iter()
+        .map(|peak| (LIMITER_CEILING / peak).min(1.))
+        .collect();
+    let mut gain = 1.;
+    let gains: Vec<f32> = (0..=allowed.len())
+        .map(|boundary| {
+            let before = boundary.checked_sub(1).map_or(1., |i| allowed[i]);
+            let after = allowed.get(boundary).copied().unwrap_or(1.);
+            gain = (gain + (1. - gain) * LIMITER_RELEASE)
+                .min(before)
+                .min(after);
+            gain
+        })
+        .collect();
+
+    for (index, block) in frames.chunks_mut(MIX_BLOCK_FRAMES).enumerate() {
+        let (from, to) = (gains[index], gains[index + 1]);
+        if from == 1. && to == 1. {
+            continue;
+        }
+        let len = block.len() as f32;
+        for (i, frame) in block.iter_mut().enumerate() {
+            *frame *= from + (to - from) * i as f32 / len;
+        }
+    }
+}
+
+fn remove_bgm_track(mut commands: Commands) {
+    commands.remove_resource::<BgmTrack>();
+    commands.remove_resource::<BgmMixing>();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(level: f32, frames: usize) -> StaticSoundData {
+        StaticSoundData {
+            sample_rate: TRACK_SAMPLE_RATE,
+            frames: vec![Frame::from_mono(level); frames].into(),
+            settings: StaticSoundSettings::default(),
+            slice: None,
+        }
+    }
+
+    #[test]
+    fn places_sounds_on_their_sample() {
+        let chunk = mix_chunk(&[(5., tone(0.5, 100))], 1);
+        let frames = &chunk.sound.frames;
+        assert_eq!(frames.len(), 4 * TRACK_SAMPLE_RATE as usize);
+        let start = TRACK_SAMPLE_RATE as usize;
+        assert_eq!(frames[start - 1], Frame::ZERO);
+        assert_eq!(frames[start], Frame::from_mono(0.5));
+        assert_eq!(frames[start + 99], Frame::from_mono(0.5));
+        assert_eq!(frames[start + 100], Frame::ZERO);
+    }
+
+    #[test]
+    fn keeps_sounds_whole_in_the_chunk_they_start_in() {
+        let rate = TRACK_SAMPLE_RATE as usize;
+        let sounds = [(3.5, tone(0.5, rate)), (4., tone(0.25, 100))];
+        let first = mix_chunk(&sounds, 0);
+        assert_eq!(first.sound.frames.len(), 4 * rate + rate / 2);
+        assert_eq!(first.sound.frames[4 * rate], Frame::from_mono(0.5));
+        let second = mix_chunk(&sounds, 1);
+        assert_eq!(second.sound.frames[0], Frame::from_mono(0.25));
+        assert_eq!(second.sound.frames[100], Frame::ZERO);
+    }
+
+    #[test]
+    fn limits_piled_up_sounds() {
+        let sounds: Vec<_> = (0..8).map(|_| (0.5, tone(0.5, 10_000))).collect();
+        let chunk = mix_chunk(&sounds, 0);
+        let peak = chunk
+            .sound
+            .frames
+            .iter()
+            .map(|frame| frame.left.abs())
+            .fold(0., f32::max);
+        assert!(peak <= LIMITER_CEILING, "{peak}");
+        assert!(peak > LIMITER_CEILING * 0.9, "{peak}");
+    }
+}
@@ -0,0 +1,98 @@
+use bevy::prelude::*;
+use bevy_kira_audio::prelude::*;
+
+use super::{bgm::BgmTrack, lane_input};
+use crate::screens::Screen;
+use crate::timing_offsets::TimingOffsets;
+
+/// Fraction of the drift from the audio clock corrected each frame.
+const CLOCK_SMOOTHING: f64 = 0.1;
+/// Drift past which the song clock jumps to the audio clock instead of easing towards it.
+const CLOCK_MAX_DRIFT: f64 = 0.05;
+
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(OnEnter(Screen::Gameplay), reset_song_clock)
+        .add_systems(
+            PreUpdate,
+            update_song_clock
+                .before(lane_input)
+                .run_if(in_state(Screen::Gameplay)),
+        )
+        .add_systems(OnExit(Screen::Gameplay), remove_song_clock);
+}
+
+/// The song position, shared by the notes, the sounds and the judgement.
+///
+/// Follows the playback position of the BGM chunks on the audio thread, shifted by the audio
+/// offset. That position only moves once per audio buffer, so the clock advances with real
+/// frame time and eases towards it, keeping the scroll smooth while staying locked to what is
+/// heard.
+#[derive(Resource, Default)]
+pub(super) struct SongClock {
+    /// Song seconds at the current frame.
+    time: f64,
+    /// `TimingOffsets::judge` as of the current frame.
+    judge_offset: f64,
+    /// The audio clock has been read at least once, it holds at 0 until the sound plays.
+    synced: bool,
+}
+
+impl SongClock {
+    /// Seconds since the song started, in the precision of the chart's timelines.
+    pub(super) fn secs(&self) -> f32 {
+        self.time as f32
+    }
+
+    /// The song time notes are drawn and judged at, `time` shifted back by the judge offset.
+    pub(super) fn judge_time(&self) -> f64 {
+        self.time - self.judge_offset
+    }
+
+    /// `judge_time` in the precision of the chart's timelines.
+    pub(super) fn judge_secs(&self) -> f32 {
+        self.judge_time() as f32
+    }
+}
+
+fn reset_song_clock(mut commands: Commands) {
+    commands.insert_resource(SongClock::default());
+}
+
+fn update_song_clock(
+    real_time: Res<Time<Real>>,
+    instances: Res<Assets<AudioInstance>>,
+    offsets: Res<TimingOffsets>,
+    track: Option<Res<BgmTrack>>,
+    mut clock: ResMut<SongClock>,
+) {
+    clock.judge_offset = offsets.judge() as f64;
+    let position = track.and_then(|track| track.position(&instances));
+    let predicted = clock.time + real_time.delta_secs_f64();
+
+    let Some(position) = position else {
+        // 最后一块放完或声音停止后（例如 gauge 归零）按帧时间继续走
+        if clock.synced {
+            clock.time = predicted;
+        }
+        return;
+    };
+    let audio_time = position + offsets.audio() as f64;
+    if !clock.synced {
+        clock.time = audio_time;
+        clock.synced = true;
+        return;
+    }
+
+    let drift = audio_time - predicted;
+    let time = if drift.abs() > CLOCK_MAX_DRIFT {
+        audio_time
+    } else {
+        predicted + drift * CLOCK_SMOOTHING
+    };
+    // 不倒退，已经过去的音符不会再出现
+    clock.time = time.max(clock.time);
+}
+
+fn remove_song_clock(mut commands: Commands) {
+    commands.remove_resource::<SongClock>();
+}
@@ -0,0 +1,378 @@
+use bevy::prelude::*;
+use bevy_kira_audio::prelude::*;
+use num_traits::ToPrimitive;
+
+use super::{
+    AppState, BmsData, ChartSetup, Judge, Judgement, LANE_HEIGHT, LaneLayout, Note,
+    OnGameplayScreen, spawn_notes,
+};
+use crate::replay::ReplayPlayback;
+use crate::resources::{GaugeType, PlayOptions};
+use crate::screens::Screen;
+
+const GAUGE_HEIGHT: f32 = 16.;
+const GAUGE_POSITION_Y: f32 = 1080. / 2. - LANE_HEIGHT - 40.;
+
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(
+        OnEnter(Screen::Gameplay),
+        (init_gauge.after(spawn_notes), spawn_gauge.after(init_gauge)).in_set(ChartSetup),
+    )
+    .add_systems(
+        Update,
+        (update_gauge, draw_gauge)
+            .chain()
+            .run_if(in_state(AppState::Playing)),
+    );
+}
+
+#[derive(Resource)]
+pub(super) struct Gauge {
+    pub kind: GaugeType,
+    /// Percentage in `0..=100`.
+    pub value: f32,
+    /// Gain per PGREAT/GREAT on recovery gauges, `#TOTAL` spread over the notes.
+    recovery: f32,
+    /// Value after each judgement, for the result graph.
+    pub history: Vec<f32>,
+}
+
+impl GaugeType {
+    fn start_value(self) -> f32 {
+        if self.is_survival() { 100. } else { 20. }
+    }
+
+    fn floor(self) -> f32 {
+        if self.is_survival() { 0. } else { 2. }
+    }
+}
+
+impl Gauge {
+    fn new(kind: GaugeType, total: f32, note_count: usize) -> Self {
+        Self {
+            kind,
+            value: kind.start_value(),
+            recovery: total / note_count.max(1) as f32,
+            history: vec![],
+        }
+    }
+
+    /// Change of the gauge for `judge`, before the HARD low-gauge damage cut.
+    fn delta(&self, judge: Judge) -> f32 {
+        let recovery = self.recovery;
+        match (self.kind, judge) {
+            (GaugeType::AssistEasy | GaugeType::Easy, Judge::PGreat | Judge::Great) => {
+                recovery * 1.2
+            }
+            (GaugeType::AssistEasy | GaugeType::Easy, Judge::Good) => recovery * 0.6,
+            (GaugeType::AssistEasy | GaugeType::Easy, Judge::Bad | Judge::EmptyPoor) => -1.6,
+            (GaugeType::AssistEasy | GaugeType::Easy, Judge::Poor) => -4.8,
+
+            (GaugeType::Normal, Judge::PGreat | Judge::Great) => recovery,
+            (GaugeType::Normal, Judge::Good) => recovery / 2.,
+            (GaugeType::Normal, Judge::Bad | Judge::EmptyPoor) => -2.,
+            (GaugeType::Normal, Judge::Poor) => -6.,
+
+            (GaugeType::Hard, Judge::PGreat | Judge::Great) => 0.16,
+            (GaugeType::Hard, Judge::Good) => 0.,
+            (GaugeType::Hard, Judge::Bad | Judge::EmptyPoor) => -5.,
+            (GaugeType::Hard, Judge::Poor) => -9.,
+
+            (GaugeType::ExHard, Judge::PGreat | Judge::Great) => 0.15,
+            (GaugeType::ExHard, Judge::Good) => 0.,
+            (GaugeType::ExHard, Judge::Bad | Judge::EmptyPoor) => -8.,
+            (GaugeType::ExHard, Judge::Poor) => -16.,
+
+            (GaugeType::Hazard, Judge::PGreat | Judge::Great) => 0.15,
+            (GaugeType::Hazard, Judge::Good) => 0.,
+            (GaugeType::Hazard, Judge::EmptyPoor) => -10.,
+            (GaugeType::Hazard, Judge::Bad | Judge::Poor) => -100.,
+        }
+    }
+
+    fn apply(&mut self, judge: Judge) {
+        let mut delta = self.delta(judge);
+        // HARD takes half damage while below 30%.
+        if self.kind == GaugeType::Hard && delta < 0. && self.value < 30. {
+            delta /= 2.;
+        }
+        self.value = (self.value + delta).clamp(self.kind.floor(), 100.);
+        self.history.push(self.value);
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.kind.is_survival() && self.value <= 0.
+    }
+
+    pub fn is_cleared(&self) -> bool {
+        if self.kind.is_survival() {
+            !self.is_failed()
+        } else {
+            self.value >= self.kind.clear_line()
+        }
+    }
+}
+
+/// The `#TOTAL` assumed when a chart doesn't declare one.
+fn default_total(note_count: usize) -> f32 {
+    let notes = note_count as f32;
+    160. + (notes + (notes - 400.).clamp(0., 200.)) * 0.16
+}
+
+#[derive(Component)]
+struct GaugeFill;
+
+#[derive(Component)]
+struct GaugeText;
+
+fn init_gauge(
+    mut commands: Commands,
+    options: Res<PlayOptions>,
+    playback: Option<Res<ReplayPlayback>>,
+    bms_data: Res<BmsData>,
+    notes: Query<&Note>,
+) {
+    let kind = playback.map_or(options.gauge, |playback| playback.replay.gauge);
+    let note_count = notes.iter().count();
+    let total = bms_data
+        .data
+        .header
+        .total
+        .as_ref()
+        .and_then(|total| total.to_f32())
+        .unwrap_or_else(|| default_total(note_count));
+    commands.insert_resource(Gauge::new(kind, total, note_count));
+}
+
+fn spawn_gauge(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    layout: Res<LaneLayout>,
+    gauge: Res<Gauge>,
+) {
+    let first = layout.groups.first().unwrap();
+    let last = layout.groups.last().unwrap();
+    let left = first.center_x - first.width / 2.;
+    let width = last.center_x + last.width / 2. - left;
+
+    commands.spawn((
+        Mesh2d(meshes.add(Rectangle::new(width, GAUGE_HEIGHT))),
+        MeshMaterial2d(materials.add(Color::srgb(0.2, 0.2, 0.2))),
+        Transform::from_translation(Vec3::new(left + width / 2., GAUGE_POSITION_Y, 0.)),
+        OnGameplayScreen,
+    ));
+
+    // 以左端为原点缩放
+    commands
+        .spawn((
+            Transform::from_translation(Vec3::new(left, GAUGE_POSITION_Y, 1.))
+                .with_scale(Vec3::new(gauge.value / 100., 1., 1.)),
+            Visibility::default(),
+            GaugeFill,
+            OnGameplayScreen,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Mesh2d(meshes.add(Rectangle::new(width, GAUGE_HEIGHT))),
+                MeshMaterial2d(materials.add(gauge.kind.color())),
+                Transform::from_translation(Vec3::new(width / 2., 0., 0.)),
+            ));
+        });
+
+    commands.spawn((
+        Text2d::new(gauge_text(&gauge)),
+        TextFont {
+            font: asset_server.load("fonts/KosugiMaru-Regular.ttf"),
+            font_size: 24.0,
+            ..default()
+        },
+        Transform::from_translation(Vec3::new(
+            left + width,
+            GAUGE_POSITION_Y - GAUGE_HEIGHT - 8.,
+            0.,
+        )),
+        bevy::sprite::Anchor::CENTER_RIGHT,
+        GaugeText,
+        OnGameplayScreen,
+    ));
+}
+
+fn gauge_text(gauge: &Gauge) -> String {
+    format!("{} {:.0}%", gauge.kind.label(), gauge.value.floor())
+}
+
+fn update_gauge(
+    mut commands: Commands,
+    mut gauge: ResMut<Gauge>,
+    mut judgements: MessageReader<Judgement>,
+    mut next_state: ResMut<NextState<AppState>>,
+    audio: Res<Audio>,
+    asset_server: Res<AssetServer>,
+) {
+    for judgement in judgements.read() {
+        gauge.apply(judgement.judge);
+        if gauge.is_failed() {
+            info!("failed with {} gauge", gauge.kind.label());
+            audio.stop();
+            next_state.set(AppState::Finished);
+            commands.spawn((
+                Text2d::new("FAILED"),
+                TextFont {
+                    font: asset_server.load("fonts/KosugiMaru-Regular.ttf"),
+                    font_size: 96.0,
+                    ..default()
+                },
+                TextColor(Color::srgb(1., 0.2, 0.2)),
+                Transform::from_translation(Vec3::new(0., 0., 10.)),
+                OnGameplayScreen,
+            ));
+            return;
+        }
+    }
+}
+
+fn draw_gauge(
+    gauge: Res<Gauge>,
+    mut fill: Query<&mut Transform, With<GaugeFill>>,
+    mut text: Query<&mut Text2d, With<GaugeText>>,
+) {
+    if !gauge.is_changed() {
+        return;
+    }
+    for mut transform in fill.iter_mut() {
+        transform.scale.x = gauge.value / 100.;
+    }
+    for mut text2d in text.iter_mut() {
+        text2d.0 = gauge_text(&gauge);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gauge(kind: GaugeType, value: f32) -> Gauge {
+        // #TOTAL 200 over 100 notes, 2% per note
+        Gauge {
+            value,
+            ..Gauge::new(kind, 200., 100)
+        }
+    }
+
+    fn after(kind: GaugeType, value: f32, judge: Judge) -> f32 {
+        let mut gauge = gauge(kind, value);
+        gauge.apply(judge);
+        gauge.value
+    }
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn default_total_grows_with_notes() {
+        assert_near(default_total(0), 160.);
+        assert_near(default_total(400), 224.);
+        assert_near(default_total(500), 256.);
+        assert_near(default_total(1000), 352.);
+    }
+
+    #[test]
+    fn start_values() {
+        for &kind in GaugeType::all() {
+            let expected = if kind.is_survival() { 100. } else { 20. };
+            assert_eq!(Gauge::new(kind, 200., 100).value, expected);
+        }
+    }
+
+    #[test]
+    fn recovery_gauges() {
+        for kind in [GaugeType::AssistEasy, GaugeType::Easy] {
+            assert_near(after(kind, 50., Judge::PGreat), 52.4);
+            assert_near(after(kind, 50., Judge::Great), 52.4);
+            assert_near(after(kind, 50., Judge::Good), 51.2);
+            assert_near(after(kind, 50., Judge::Bad), 48.4);
+            assert_near(after(kind, 50., Judge::EmptyPoor), 48.4);
+            assert_near(after(kind, 50., Judge::Poor), 45.2);
+        }
+
+        let kind = GaugeType::Normal;
+        assert_near(after(kind, 50., Judge::PGreat), 52.);
+        assert_near(after(kind, 50., Judge::Great), 52.);
+        assert_near(after(kind, 50., Judge::Good), 51.);
+        assert_near(after(kind, 50., Judge::Bad), 48.);
+        assert_near(after(kind, 50., Judge::EmptyPoor), 48.);
+        assert_near(after(kind, 50., Judge::Poor), 44.);
+
+        // 不低于 2%，不高于 100%
+        assert_near(after(kind, 3., Judge::Poor), 2.);
+        assert_near(after(kind, 99., Judge::PGreat), 100.);
+    }
+
+    #[test]
+    fn recovery_gauge_clear_lines() {
+        assert!(gauge(GaugeType::AssistEasy, 60.).is_cleared());
+        assert!(!gauge(GaugeType::AssistEasy, 59.9).is_cleared());
+        for kind in [GaugeType::Easy, GaugeType::Normal] {
+            assert!(gauge(kind, 80.).is_cleared());
+            assert!(!gauge(kind, 79.9).is_cleared());
+        }
+        assert!(!gauge(GaugeType::Normal, 2.).is_failed());
+    }
+
+    #[test]
+    fn survival_gauges() {
+        let kind = GaugeType::Hard;
+        assert_near(after(kind, 50., Judge::PGreat), 50.16);
+        assert_near(after(kind, 50., Judge::Good), 50.);
+        assert_near(after(kind, 50., Judge::Bad), 45.);
+        assert_near(after(kind, 50., Judge::Poor), 41.);
+
+        let kind = GaugeType::ExHard;
+        assert_near(after(kind, 50., Judge::Great), 50.15);
+        assert_near(after(kind, 50., Judge::Good), 50.);
+        assert_near(after(kind, 50., Judge::EmptyPoor), 42.);
+        assert_near(after(kind, 50., Judge::Poor), 34.);
+
+        let kind = GaugeType::Hazard;
+        assert_near(after(kind, 50., Judge::PGreat), 50.15);
+        assert_near(after(kind, 50., Judge::EmptyPoor), 40.);
+        assert_near(after(kind, 100., Judge::Bad), 0.);
+        assert_near(after(kind, 100., Judge::Poor), 0.);
+    }
+
+    #[test]
+    fn hard_takes_half_damage_below_30() {
+        let kind = GaugeType::Hard;
+        assert_near(after(kind, 30., Judge::Poor), 21.);
+        assert_near(after(kind, 29., Judge::Poor), 24.5);
+        assert_near(after(kind, 29., Judge::Bad), 26.5);
+        // 回复不受影响
+        assert_near(after(kind, 29., Judge::PGreat), 29.16);
+        assert_near(after(GaugeType::ExHard, 29., Judge::Poor), 13.);
+    }
+
+    #[test]
+    fn survival_gauges_fail_at_zero() {
+        for kind in [GaugeType::Hard, GaugeType::ExHard, GaugeType::Hazard] {
+            let mut gauge = gauge(kind, 100.);
+            assert!(gauge.is_cleared());
+            while !gauge.is_failed() {
+                gauge.apply(Judge::Poor);
+            }
+            assert_eq!(gauge.value, 0.);
+            assert!(!gauge.is_cleared());
+        }
+
+        let mut gauge = gauge(GaugeType::Hard, 0.1);
+        gauge.apply(Judge::PGreat);
+        assert!(!gauge.is_failed());
+    }
+}
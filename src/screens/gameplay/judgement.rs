@@ -0,0 +1,353 @@
+use bevy::prelude::*;
+
+use bms_rs::command::JudgeLevel;
+
+use super::{AppState, ChartSetup, JUDGEMENTLINE_POSITION, Lane, LaneLayout, OnGameplayScreen};
+use crate::chart::JudgeRank;
+use crate::resources::PlayOptions;
+use crate::screens::Screen;
+
+const JUDGEMENT_TEXT_OFFSET_Y: f32 = 200.;
+const FAST_SLOW_TEXT_OFFSET_Y: f32 = -45.;
+const JUDGEMENT_TEXT_DURATION: f32 = 1.;
+
+pub(super) fn plugin(app: &mut App) {
+    app.add_message::<Judgement>()
+        .add_systems(
+            OnEnter(Screen::Gameplay),
+            spawn_judgement_text.in_set(ChartSetup),
+        )
+        .add_systems(Update, show_judgement.run_if(in_state(AppState::Playing)));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) enum Judge {
+    PGreat,
+    Great,
+    Good,
+    Bad,
+    /// A note that passed the judgement line without being hit.
+    Poor,
+    /// A press too early to hit the note, which stays judgeable.
+    EmptyPoor,
+}
+
+impl Judge {
+    fn label(self) -> &'static str {
+        match self {
+            Judge::PGreat => "PGREAT",
+            Judge::Great => "GREAT",
+            Judge::Good => "GOOD",
+            Judge::Bad => "BAD",
+            Judge::Poor | Judge::EmptyPoor => "POOR",
+        }
+    }
+
+    /// Whether the judged note is used up, an empty POOR leaves it judgeable.
+    pub(super) fn consumes_note(self) -> bool {
+        self != Judge::EmptyPoor
+    }
+
+    pub(super) fn color(self) -> Color {
+        match self {
+            Judge::PGreat => Color::srgb(0.5, 1., 1.),
+            Judge::Great => Color::srgb(1., 1., 0.),
+            Judge::Good => Color::srgb(0., 1., 0.),
+            Judge::Bad => Color::srgb(0.5, 0.5, 1.),
+            Judge::Poor | Judge::EmptyPoor => Color::srgb(1., 0., 0.),
+        }
+    }
+}
+
+#[derive(Message, Debug, Clone, Copy)]
+pub(super) struct Judgement {
+    pub lane: Lane,
+    pub judge: Judge,
+    /// Seconds from the note to the press, negative when early. Zero for missed notes.
+    pub offset: f32,
+}
+
+/// Half widths of each judgement window in seconds.
+#[derive(Resource, Debug, Clone, Copy)]
+pub(super) struct TimingWindow {
+    pub pgreat: f32,
+    pub great: f32,
+    pub good: f32,
+    pub bad: f32,
+    /// How early a press can be and still get an empty POOR.
+    pub poor: f32,
+}
+
+const fn timing_window(pgreat_ms: f32, great_ms: f32, good_ms: f32) -> TimingWindow {
+    TimingWindow {
+        pgreat: pgreat_ms / 1000.,
+        great: great_ms / 1000.,
+        good: good_ms / 1000.,
+        bad: 200. / 1000.,
+        poor: 1.,
+    }
+}
+
+const VERY_HARD_WINDOW: TimingWindow = timing_window(8., 24., 40.);
+const HARD_WINDOW: TimingWindow = timing_window(15., 30., 60.);
+const NORMAL_WINDOW: TimingWindow = timing_window(18., 40., 100.);
+const EASY_WINDOW: TimingWindow = timing_window(21., 60., 120.);
+
+impl TimingWindow {
+    pub fn for_rank(judge_rank: JudgeRank) -> Self {
+        match judge_rank {
+            JudgeRank::Rank(JudgeLevel::VeryHard) => VERY_HARD_WINDOW,
+            JudgeRank::Rank(JudgeLevel::Hard) => HARD_WINDOW,
+            JudgeRank::Rank(JudgeLevel::Normal) => NORMAL_WINDOW,
+            JudgeRank::Rank(JudgeLevel::Easy) => EASY_WINDOW,
+            JudgeRank::Rank(JudgeLevel::OtherInt(rank)) if rank < 0 => VERY_HARD_WINDOW,
+            JudgeRank::Rank(JudgeLevel::OtherInt(_)) => EASY_WINDOW,
+            JudgeRank::DefExRank(percent) => {
+                // BAD and empty POOR stay fixed, like #RANK does.
+                let scale = percent as f32 / 100.;
+                TimingWindow {
+                    pgreat: NORMAL_WINDOW.pgreat * scale,
+                    great: NORMAL_WINDOW.great * scale,
+                    good: (NORMAL_WINDOW.good * scale).min(NORMAL_WINDOW.bad),
+                    ..NORMAL_WINDOW
+                }
+            }
+        }
+    }
+
+    /// Judges a press `offset` seconds away from a note, `None` if the note is out of reach.
+    pub fn judge(&self, offset: f32) -> Option<Judge> {
+        let distance = offset.abs();
+        if distance <= self.pgreat {
+            Some(Judge::PGreat)
+        } else if distance <= self.great {
+            Some(Judge::Great)
+        } else if distance <= self.good {
+            Some(Judge::Good)
+        } else if distance <= self.bad {
+            Some(Judge::Bad)
+        } else if offset < 0. && distance <= self.poor {
+            Some(Judge::EmptyPoor)
+        } else {
+            None
+        }
+    }
+
+    /// Judges a press at `time` against the closest of a lane's notes, each given as a key and
+    /// its song time. Notes already past BAD are left to be missed. Returns the key of the note
+    /// with the judge and offset, `None` when no note is in reach.
+    pub fn judge_press<T>(
+        &self,
+        notes: impl IntoIterator<Item = (T, f32)>,
+        time: f32,
+    ) -> Option<(T, Judge, f32)> {
+        let (key, note_time) = notes
+            .into_iter()
+            .filter(|(_, note_time)| *note_time >= time - self.bad)
+            .min_by(|(_, a), (_, b)| (a - time).abs().total_cmp(&(b - time).abs()))?;
+        let offset = time - note_time;
+        self.judge(offset).map(|judge| (key, judge, offset))
+    }
+}
+
+#[derive(Component)]
+struct JudgementText {
+    group: usize,
+    until: f32,
+}
+
+/// FAST/SLOW under a judgement text, as its child.
+#[derive(Component)]
+struct FastSlowText;
+
+impl Judgement {
+    /// FAST/SLOW for GREAT and below, with the offset in milliseconds when `with_ms`.
+    fn fast_slow(&self, with_ms: bool) -> Option<(String, Color)> {
+        let ms = self.offset * 1000.;
+        let (label, color) = match self.judge {
+            Judge::Poor | Judge::EmptyPoor => return None,
+            Judge::PGreat if !with_ms => return None,
+            Judge::PGreat => ("", Color::WHITE),
+            _ if ms < 0. => ("FAST ", Color::srgb(0.3, 0.6, 1.)),
+            _ if ms > 0. => ("SLOW ", Color::srgb(1., 0.4, 0.3)),
+            _ => return None,
+        };
+        let text = if with_ms {
+            format!("{}{:+.0}ms", label, ms)
+        } else {
+            label.trim_end().to_string()
+        };
+        Some((text, color))
+    }
+}
+
+fn spawn_judgement_text(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    layout: Res<LaneLayout>,
+) {
+    let text_font = TextFont {
+        font: asset_server.load("fonts/KosugiMaru-Regular.ttf"),
+        font_size: 50.0,
+        ..default()
+    };
+
+    let fast_slow_font = TextFont {
+        font_size: 30.0,
+        ..text_font.clone()
+    };
+
+    for (group_index, group) in layout.groups.iter().enumerate() {
+        commands.spawn((
+            children![(
+                Text2d::new(""),
+                fast_slow_font.clone(),
+                TextColor::WHITE,
+                Transform::from_translation(Vec3::new(0., FAST_SLOW_TEXT_OFFSET_Y, 0.)),
+                FastSlowText,
+            )],
+            Text2d::new(""),
+            text_font.clone(),
+            TextColor::WHITE,
+            Transform::from_translation(Vec3::new(
+                group.center_x,
+                JUDGEMENTLINE_POSITION.y + JUDGEMENT_TEXT_OFFSET_Y,
+                2.,
+            )),
+            Visibility::Hidden,
+            JudgementText {
+                group: group_index,
+                until: 0.,
+            },
+            OnGameplayScreen,
+        ));
+    }
+}
+
+fn show_judgement(
+    time: Res<Time>,
+    layout: Res<LaneLayout>,
+    options: Res<PlayOptions>,
+    mut judgements: MessageReader<Judgement>,
+    mut texts: Query<
+        (
+            &mut JudgementText,
+            &mut Text2d,
+            &mut TextColor,
+            &mut Visibility,
+            &Children,
+        ),
+        Without<FastSlowText>,
+    >,
+    mut fast_slow_texts: Query<(&mut Text2d, &mut TextColor), With<FastSlowText>>,
+) {
+    let now = time.elapsed_secs();
+
+    for judgement in judgements.read() {
+        let group = layout.group_index(judgement.lane);
+        for (mut judgement_text, mut text2d, mut color, _, children) in texts.iter_mut() {
+            if judgement_text.group == group {
+                text2d.0 = judgement.judge.label().to_string();
+                color.0 = judgement.judge.color();
+                judgement_text.until = now + JUDGEMENT_TEXT_DURATION;
+
+                let fast_slow = judgement.fast_slow(options.offset_ms);
+                for child in children.iter() {
+                    if let Ok((mut text2d, mut color)) = fast_slow_texts.get_mut(child) {
+                        (text2d.0, color.0) = fast_slow.clone().unwrap_or_default();
+                    }
+                }
+            }
+        }
+    }
+
+    for (judgement_text, _, _, mut visibility, _) in texts.iter_mut() {
+        *visibility = if now < judgement_text.until {
+            Visibility::Visible
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: f32) -> f32 {
+        ms / 1000.
+    }
+
+    /// PGREAT, GREAT and GOOD edges of `window`, each checked on and just past the edge.
+    fn assert_window(window: &TimingWindow, pgreat: f32, great: f32, good: f32) {
+        for sign in [-1., 1.] {
+            let judge = |offset_ms: f32| window.judge(sign * ms(offset_ms));
+            assert_eq!(judge(0.), Some(Judge::PGreat));
+            assert_eq!(judge(pgreat), Some(Judge::PGreat));
+            assert_eq!(judge(pgreat + 0.5), Some(Judge::Great));
+            assert_eq!(judge(great), Some(Judge::Great));
+            assert_eq!(judge(great + 0.5), Some(Judge::Good));
+            assert_eq!(judge(good), Some(Judge::Good));
+            assert_eq!(judge(good + 0.5), Some(Judge::Bad));
+            assert_eq!(judge(200.), Some(Judge::Bad));
+        }
+    }
+
+    #[test]
+    fn rank_windows() {
+        let rank = |level| TimingWindow::for_rank(JudgeRank::Rank(level));
+        assert_window(&rank(JudgeLevel::VeryHard), 8., 24., 40.);
+        assert_window(&rank(JudgeLevel::Hard), 15., 30., 60.);
+        assert_window(&rank(JudgeLevel::Normal), 18., 40., 100.);
+        assert_window(&rank(JudgeLevel::Easy), 21., 60., 120.);
+        assert_window(&rank(JudgeLevel::OtherInt(-1)), 8., 24., 40.);
+        assert_window(&rank(JudgeLevel::OtherInt(4)), 21., 60., 120.);
+    }
+
+    #[test]
+    fn bad_and_empty_poor_edges() {
+        let window = TimingWindow::for_rank(JudgeRank::Rank(JudgeLevel::Normal));
+        assert_eq!(window.judge(ms(200.5)), None);
+        assert_eq!(window.judge(-ms(200.5)), Some(Judge::EmptyPoor));
+        assert_eq!(window.judge(-1.), Some(Judge::EmptyPoor));
+        assert_eq!(window.judge(-1.001), None);
+        assert_eq!(window.judge(1.), None);
+    }
+
+    #[test]
+    fn judge_press_picks_the_closest_note_in_reach() {
+        let window = TimingWindow::for_rank(JudgeRank::Rank(JudgeLevel::Normal));
+        let notes = [(0, 1.), (1, 1.5), (2, 2.)];
+
+        assert_eq!(
+            window.judge_press(notes, 1.01),
+            Some((0, Judge::PGreat, 1.01 - 1.))
+        );
+        assert_eq!(window.judge_press(notes, 1.3).map(|(key, ..)| key), Some(1));
+        // 已经超过 BAD 的音符留给漏判，按键判到下一个音符上
+        assert_eq!(
+            window.judge_press(notes, 1.75),
+            Some((2, Judge::EmptyPoor, 1.75 - 2.))
+        );
+        assert_eq!(window.judge_press(notes, 2.3), None);
+        assert_eq!(window.judge_press(Vec::<(usize, f32)>::new(), 1.), None);
+    }
+
+    #[test]
+    fn def_ex_rank_scales_normal() {
+        let window = TimingWindow::for_rank(JudgeRank::DefExRank(100));
+        assert_window(&window, 18., 40., 100.);
+
+        let window = TimingWindow::for_rank(JudgeRank::DefExRank(50));
+        assert!((window.pgreat - ms(9.)).abs() < 1e-6);
+        assert!((window.great - ms(20.)).abs() < 1e-6);
+        assert!((window.good - ms(50.)).abs() < 1e-6);
+        assert_eq!(window.bad, ms(200.));
+        assert_eq!(window.poor, 1.);
+
+        // GOOD never reaches past BAD
+        let window = TimingWindow::for_rank(JudgeRank::DefExRank(300));
+        assert_eq!(window.good, window.bad);
+        assert_eq!(window.judge(ms(200.5)), None);
+    }
+}
@@ -0,0 +1,83 @@
+use bevy::prelude::*;
+
+use super::{ChartSetup, LaneLayout, OnGameplayScreen};
+use crate::screens::{
+    Screen,
+    bindings::{Binding, KeyBindings},
+};
+use crate::timing_offsets::TimingOffsets;
+
+const OFFSET_TEXT_POSITION_Y: f32 = -460.;
+
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(
+        OnEnter(Screen::Gameplay),
+        spawn_offset_text.in_set(ChartSetup),
+    )
+    .add_systems(
+        Update,
+        adjust_offsets.run_if(in_state(Screen::Gameplay).and(resource_exists::<LaneLayout>)),
+    );
+}
+
+#[derive(Component)]
+struct OffsetText;
+
+fn spawn_offset_text(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    offsets: Res<TimingOffsets>,
+) {
+    commands.spawn((
+        Text2d::new(offsets.label()),
+        TextFont {
+            font: asset_server.load("fonts/KosugiMaru-Regular.ttf"),
+            font_size: 24.0,
+            ..default()
+        },
+        Transform::from_translation(Vec3::new(0., OFFSET_TEXT_POSITION_Y, 0.)),
+        OffsetText,
+        OnGameplayScreen,
+    ));
+}
+
+/// Up/Down nudge the judge offset and PageUp/PageDown the audio offset by 1 ms, 10 ms with
+/// Shift held, unless those keys are bound to lanes.
+fn adjust_offsets(
+    keys: Res<ButtonInput<KeyCode>>,
+    layout: Res<LaneLayout>,
+    bindings: Res<KeyBindings>,
+    mut offsets: ResMut<TimingOffsets>,
+    mut text: Single<&mut Text2d, With<OffsetText>>,
+) {
+    let lane_keys = layout.lane_keys(&bindings);
+    let pressed = |key: KeyCode| {
+        keys.just_pressed(key)
+            && !lane_keys
+                .values()
+                .any(|bindings| bindings.contains(&Binding::Key(key)))
+    };
+    let step = if keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        10
+    } else {
+        1
+    };
+
+    let before = *offsets;
+    if pressed(KeyCode::ArrowUp) {
+        offsets.judge_ms += step;
+    }
+    if pressed(KeyCode::ArrowDown) {
+        offsets.judge_ms -= step;
+    }
+    if pressed(KeyCode::PageUp) {
+        offsets.audio_ms += step;
+    }
+    if pressed(KeyCode::PageDown) {
+        offsets.audio_ms -= step;
+    }
+    if *offsets != before {
+        offsets.save();
+        text.0 = offsets.label();
+    }
+}
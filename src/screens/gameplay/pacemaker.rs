@@ -0,0 +1,238 @@
+use bevy::prelude::*;
+
+use super::{
+    AppState, BOTTOM_BORDER_POSITION, BmsData, ChartSetup, Judge, LANE_HEIGHT, Lane, LaneLayout,
+    Note, OnGameplayScreen, PlayStyle, SongClock, TimingWindow, score::Score, spawn_notes,
+};
+use crate::chart::parse_chart;
+use crate::replay::Replay;
+use crate::resources::{self, BmsLib, PacemakerTarget, PlayOptions};
+use crate::score_store::ScoreStore;
+use crate::screens::Screen;
+
+const PACEMAKER_BAR_WIDTH: f32 = 24.;
+const PACEMAKER_MARGIN: f32 = 40.;
+
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(
+        OnEnter(Screen::Gameplay),
+        (
+            init_pacemaker.after(spawn_notes),
+            spawn_pacemaker
+                .after(init_pacemaker)
+                .run_if(resource_exists::<Pacemaker>),
+        )
+            .in_set(ChartSetup),
+    )
+    .add_systems(
+        Update,
+        update_pacemaker.run_if(in_state(AppState::Playing).and(resource_exists::<Pacemaker>)),
+    )
+    .add_systems(OnExit(Screen::Gameplay), remove_pacemaker);
+}
+
+#[derive(Resource)]
+struct Pacemaker {
+    target: PacemakerTarget,
+    /// EX score rate to keep pace with, for grade and personal best targets.
+    rate: f32,
+    /// Cumulative EX score of the replay by judge time.
+    timeline: Vec<(f32, u32)>,
+}
+
+impl Pacemaker {
+    /// The target's EX score at this point of the play.
+    fn target_ex_score(&self, score: &Score, elapsed: f32) -> u32 {
+        match self.target {
+            PacemakerTarget::Replay => {
+                let reached = self.timeline.partition_point(|(time, _)| *time <= elapsed);
+                reached
+                    .checked_sub(1)
+                    .map_or(0, |index| self.timeline[index].1)
+            }
+            _ => {
+                let judged = score.pgreat + score.great + score.good + score.bad + score.poor;
+                (self.rate * (judged * 2) as f32).ceil() as u32
+            }
+        }
+    }
+}
+
+/// Re-judges the replay presses against the notes offline, for the EX score it had over time.
+fn replay_timeline(
+    replay: &Replay,
+    notes: &[(Lane, f32)],
+    window: &TimingWindow,
+) -> Vec<(f32, u32)> {
+    let mut remaining = notes.to_vec();
+    let (mut pgreat, mut great) = (0, 0);
+    let mut timeline = vec![];
+
+    for event in replay.events.iter().filter(|event| event.pressed) {
+        let lane = Lane::all()[event.lane as usize];
+        let lane_notes = remaining
+            .iter()
+            .enumerate()
+            .filter(|(_, (note_lane, _))| *note_lane == lane)
+            .map(|(index, (_, time))| (index, *time));
+        let Some((index, judge, _)) = window.judge_press(lane_notes, event.time) else {
+            continue;
+        };
+        if judge.consumes_note() {
+            remaining.remove(index);
+        }
+        match judge {
+            Judge::PGreat => pgreat += 1,
+            Judge::Great => great += 1,
+            _ => {}
+        }
+        timeline.push((event.time, resources::ex_score(pgreat, great)));
+    }
+
+    timeline
+}
+
+#[derive(Component)]
+struct PacemakerBar {
+    /// The player's bar rather than the target's.
+    player: bool,
+}
+
+#[derive(Component)]
+struct PacemakerText;
+
+/// Whether the replay drew the same `#RANDOM` values as this play, so it played these notes.
+fn same_notes(replay: &Replay, lib: &BmsLib, bms_data: &BmsData) -> bool {
+    if bms_data.random_values.is_empty() {
+        return true;
+    }
+    let path = &lib.cursor_entry().unwrap().path;
+    parse_chart(path, replay.random_seed)
+        .is_ok_and(|chart| chart.random_values == bms_data.random_values)
+}
+
+/// Sets up the pacemaker, none when racing a replay that played different notes or no replay.
+fn init_pacemaker(
+    mut commands: Commands,
+    options: Res<PlayOptions>,
+    lib: Res<BmsLib>,
+    bms_data: Res<BmsData>,
+    store: Res<ScoreStore>,
+    timing_window: Res<TimingWindow>,
+    notes: Query<&Note>,
+) {
+    let hash = &lib.cursor_entry().unwrap().hash;
+    let (rate, timeline) = match options.pacemaker {
+        PacemakerTarget::Grade(level) => (level.min_rate(), vec![]),
+        PacemakerTarget::PersonalBest => {
+            let rate = store.get(hash).map_or(0., |record| {
+                record.best_ex_score as f32 / record.max_ex_score.max(1) as f32
+            });
+            (rate, vec![])
+        }
+        PacemakerTarget::Replay => {
+            let Some(replay) = Replay::load_latest(hash) else {
+                info!("no replay to pace against for {}", hash);
+                return;
+            };
+            // 按当前谱面的音符重新判定，#RANDOM 抽到别的分支时就不是同一组音符
+            if !same_notes(&replay, &lib, &bms_data) {
+                info!("the last replay of {} drew other #RANDOM values", hash);
+                return;
+            }
+            let notes = notes
+                .iter()
+                .map(|note| (note.lane, note.time))
+                .collect::<Vec<_>>();
+            (0., replay_timeline(&replay, &notes, &timing_window))
+        }
+    };
+
+    commands.insert_resource(Pacemaker {
+        target: options.pacemaker,
+        rate,
+        timeline,
+    });
+}
+
+fn spawn_pacemaker(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    layout: Res<LaneLayout>,
+    pacemaker: Res<Pacemaker>,
+) {
+    // 放在 BGA 的另一侧
+    let first = layout.groups.first().unwrap();
+    let last = layout.groups.last().unwrap();
+    let x = if layout.style == PlayStyle::Single1P {
+        first.center_x - first.width / 2. - PACEMAKER_MARGIN - PACEMAKER_BAR_WIDTH
+    } else {
+        last.center_x + last.width / 2. + PACEMAKER_MARGIN + PACEMAKER_BAR_WIDTH
+    };
+    let bottom = BOTTOM_BORDER_POSITION.y;
+
+    for (player, offset_x, color) in [
+        (true, -PACEMAKER_BAR_WIDTH / 2., Color::srgb(0.3, 0.8, 1.)),
+        (false, PACEMAKER_BAR_WIDTH / 2., Color::srgb(1., 0.6, 0.2)),
+    ] {
+        // 以底边为原点缩放
+        commands
+            .spawn((
+                Transform::from_translation(Vec3::new(x + offset_x, bottom, 0.))
+                    .with_scale(Vec3::new(1., 0., 1.)),
+                Visibility::default(),
+                PacemakerBar { player },
+                OnGameplayScreen,
+            ))
+            .with_children(|parent| {
+                parent.spawn((
+                    Mesh2d(meshes.add(Rectangle::new(PACEMAKER_BAR_WIDTH - 4., LANE_HEIGHT))),
+                    MeshMaterial2d(materials.add(color)),
+                    Transform::from_translation(Vec3::new(0., LANE_HEIGHT / 2., 0.)),
+                ));
+            });
+    }
+
+    commands.spawn((
+        Text2d::new(pacemaker.target.label()),
+        TextFont {
+            font: asset_server.load("fonts/KosugiMaru-Regular.ttf"),
+            font_size: 20.0,
+            ..default()
+        },
+        Transform::from_translation(Vec3::new(x, bottom - 20., 0.)),
+        PacemakerText,
+        OnGameplayScreen,
+    ));
+}
+
+fn update_pacemaker(
+    clock: Res<SongClock>,
+    score: Res<Score>,
+    pacemaker: Res<Pacemaker>,
+    mut bars: Query<(&mut Transform, &PacemakerBar)>,
+    mut texts: Query<&mut Text2d, With<PacemakerText>>,
+) {
+    let elapsed = clock.judge_secs();
+    let max_ex_score = score.max_ex_score().max(1) as f32;
+    let ex_score = score.ex_score();
+    let target = pacemaker.target_ex_score(&score, elapsed);
+
+    for (mut transform, bar) in bars.iter_mut() {
+        let value = if bar.player { ex_score } else { target };
+        transform.scale.y = value as f32 / max_ex_score;
+    }
+    for mut text2d in texts.iter_mut() {
+        text2d.0 = format!(
+            "{} {:+}",
+            pacemaker.target.label(),
+            ex_score as i64 - target as i64
+        );
+    }
+}
+
+fn remove_pacemaker(mut commands: Commands) {
+    commands.remove_resource::<Pacemaker>();
+}
@@ -0,0 +1,98 @@
+use bevy::prelude::*;
+
+use super::{
+    AppState, Autoplay, ChartSetup, Lane, LaneInput, PlayStatus, SongClock, judge_input,
+    spawn_notes,
+};
+use crate::replay::{Replay, ReplayEvent, ReplayPlayback};
+use crate::resources::{BmsLib, PlayOptions};
+use crate::screens::Screen;
+
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(
+        OnEnter(Screen::Gameplay),
+        (
+            start_recording,
+            apply_replay_speed
+                .after(spawn_notes)
+                .in_set(ChartSetup)
+                .run_if(resource_exists::<ReplayPlayback>),
+        ),
+    )
+    .add_systems(
+        Update,
+        (
+            play_replay
+                .before(judge_input)
+                .run_if(resource_exists::<ReplayPlayback>),
+            record_input.after(judge_input),
+        )
+            .run_if(in_state(AppState::Playing)),
+    )
+    .add_systems(OnEnter(AppState::Finished), save_replay);
+}
+
+/// Lane inputs of the current play.
+#[derive(Resource, Default)]
+struct ReplayRecorder {
+    events: Vec<ReplayEvent>,
+}
+
+fn start_recording(mut commands: Commands) {
+    commands.insert_resource(ReplayRecorder::default());
+}
+
+fn apply_replay_speed(playback: Res<ReplayPlayback>, mut status: ResMut<PlayStatus>) {
+    status.green_number = playback.replay.green_number;
+}
+
+fn play_replay(
+    clock: Res<SongClock>,
+    mut playback: ResMut<ReplayPlayback>,
+    mut inputs: MessageWriter<LaneInput>,
+) {
+    let elapsed = clock.judge_secs();
+
+    while let Some(event) = playback.replay.events.get(playback.next).copied() {
+        if event.time > elapsed {
+            break;
+        }
+        playback.next += 1;
+        inputs.write(LaneInput {
+            lane: Lane::all()[event.lane as usize],
+            pressed: event.pressed,
+            time: event.time,
+        });
+    }
+}
+
+fn record_input(mut recorder: ResMut<ReplayRecorder>, mut inputs: MessageReader<LaneInput>) {
+    for input in inputs.read() {
+        recorder.events.push(ReplayEvent {
+            time: input.time,
+            lane: input.lane as u8,
+            pressed: input.pressed,
+        });
+    }
+}
+
+fn save_replay(
+    recorder: Res<ReplayRecorder>,
+    lib: Res<BmsLib>,
+    options: Res<PlayOptions>,
+    status: Res<PlayStatus>,
+    playback: Option<Res<ReplayPlayback>>,
+    autoplay: Option<Res<Autoplay>>,
+) {
+    if playback.is_some() || autoplay.is_some() {
+        return;
+    }
+    Replay {
+        chart_hash: lib.cursor_entry().unwrap().hash.clone(),
+        random_seed: options.random_seed,
+        gauge: options.gauge,
+        green_number: status.green_number,
+        events: recorder.events.clone(),
+    }
+    .save();
+}
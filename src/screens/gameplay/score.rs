@@ -0,0 +1,160 @@
+use bevy::prelude::*;
+
+use super::{
+    AppState, ChartSetup, Judge, Judgement, LaneLayout, Note, OnGameplayScreen, spawn_notes,
+};
+use crate::resources::{self, DjLevel};
+use crate::screens::Screen;
+
+const SCORE_TEXT_POSITION_Y: f32 = -500.;
+
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(
+        OnEnter(Screen::Gameplay),
+        spawn_score.after(spawn_notes).in_set(ChartSetup),
+    )
+    .add_systems(
+        Update,
+        (update_score, draw_score)
+            .chain()
+            .run_if(in_state(AppState::Playing)),
+    );
+}
+
+/// Judgement counts and combo of the current play.
+#[derive(Resource, Debug, Default)]
+pub(super) struct Score {
+    pub notes: usize,
+    pub pgreat: u32,
+    pub great: u32,
+    pub good: u32,
+    pub bad: u32,
+    pub poor: u32,
+    pub empty_poor: u32,
+    pub combo: u32,
+    pub max_combo: u32,
+    pub fast: u32,
+    pub slow: u32,
+    /// Signed offset in milliseconds of every hit note.
+    pub offsets: Vec<f32>,
+}
+
+impl Score {
+    fn new(notes: usize) -> Self {
+        Self { notes, ..default() }
+    }
+
+    fn add(&mut self, judgement: &Judgement) {
+        let judge = judgement.judge;
+        match judge {
+            Judge::PGreat => self.pgreat += 1,
+            Judge::Great => self.great += 1,
+            Judge::Good => self.good += 1,
+            Judge::Bad => self.bad += 1,
+            Judge::Poor => self.poor += 1,
+            Judge::EmptyPoor => self.empty_poor += 1,
+        }
+        match judge {
+            Judge::PGreat | Judge::Great | Judge::Good => {
+                self.combo += 1;
+                self.max_combo = self.max_combo.max(self.combo);
+            }
+            Judge::Bad | Judge::Poor => self.combo = 0,
+            // 空POOR 不断连
+            Judge::EmptyPoor => {}
+        }
+        if !matches!(judge, Judge::Poor | Judge::EmptyPoor) {
+            self.offsets.push(judgement.offset * 1000.);
+        }
+        if matches!(judge, Judge::Great | Judge::Good | Judge::Bad) {
+            if judgement.offset < 0. {
+                self.fast += 1;
+            } else if judgement.offset > 0. {
+                self.slow += 1;
+            }
+        }
+    }
+
+    pub fn ex_score(&self) -> u32 {
+        resources::ex_score(self.pgreat, self.great)
+    }
+
+    pub fn max_ex_score(&self) -> u32 {
+        resources::max_ex_score(self.notes)
+    }
+
+    /// BAD and POOR on notes, each one resets the combo.
+    pub fn combo_breaks(&self) -> u32 {
+        self.bad + self.poor
+    }
+
+    pub fn dj_level(&self) -> DjLevel {
+        DjLevel::new(self.ex_score(), self.max_ex_score())
+    }
+}
+
+#[derive(Component)]
+struct ScoreText;
+
+fn spawn_score(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    layout: Res<LaneLayout>,
+    notes: Query<&Note>,
+) {
+    let score = Score::new(notes.iter().count());
+
+    let first = layout.groups.first().unwrap();
+    commands.spawn((
+        Text2d::new(score_text(&score)),
+        TextFont {
+            font: asset_server.load("fonts/KosugiMaru-Regular.ttf"),
+            font_size: 24.0,
+            ..default()
+        },
+        TextLayout::new_with_justify(Justify::Left),
+        Transform::from_translation(Vec3::new(
+            first.center_x - first.width / 2.,
+            SCORE_TEXT_POSITION_Y,
+            0.,
+        )),
+        bevy::sprite::Anchor::CENTER_LEFT,
+        ScoreText,
+        OnGameplayScreen,
+    ));
+
+    commands.insert_resource(score);
+}
+
+fn score_text(score: &Score) -> String {
+    format!(
+        "EX SCORE {} / {}  COMBO {} (MAX {})  {}\nPG {} GR {} GD {} BD {} PR {} ({})  CB {}",
+        score.ex_score(),
+        score.max_ex_score(),
+        score.combo,
+        score.max_combo,
+        score.dj_level().label(),
+        score.pgreat,
+        score.great,
+        score.good,
+        score.bad,
+        score.poor,
+        score.empty_poor,
+        score.combo_breaks(),
+    )
+}
+
+fn update_score(mut score: ResMut<Score>, mut judgements: MessageReader<Judgement>) {
+    for judgement in judgements.read() {
+        score.add(judgement);
+    }
+}
+
+fn draw_score(score: Res<Score>, mut texts: Query<&mut Text2d, With<ScoreText>>) {
+    if !score.is_changed() {
+        return;
+    }
+    for mut text2d in texts.iter_mut() {
+        text2d.0 = score_text(&score);
+    }
+}
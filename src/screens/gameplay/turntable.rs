@@ -0,0 +1,187 @@
+use bevy::{
+    input::mouse::MouseMotion,
+    platform::collections::HashMap,
+    prelude::*,
+    window::{CursorGrabMode, CursorOptions, PrimaryWindow},
+};
+
+use super::{AppState, Autoplay, Lane, LaneInput, LaneLayout, SongClock, judge_input};
+use crate::replay::ReplayPlayback;
+use crate::screens::{
+    Screen,
+    bindings::{KeyBindings, MouseAxis},
+};
+
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(OnEnter(Screen::Gameplay), reset_turntables)
+        .add_systems(OnEnter(AppState::Playing), grab_cursor)
+        .add_systems(OnExit(Screen::Gameplay), release_cursor)
+        .add_systems(
+            Update,
+            (turntable_input, mouse_turntable_input)
+                .before(judge_input)
+                .run_if(
+                    in_state(AppState::Playing)
+                        .and(not(resource_exists::<Autoplay>))
+                        .and(not(resource_exists::<ReplayPlayback>)),
+                ),
+        );
+}
+
+/// Press state of a scratch lane driven by continuous movement instead of a button.
+#[derive(Default)]
+struct Scratch {
+    /// Turning direction of the current press, 0 while released.
+    direction: f32,
+    /// Song time of the last movement past the threshold.
+    last_move: f32,
+}
+
+impl Scratch {
+    /// Feeds the movement since the last reading. Presses `lane` when it starts turning or
+    /// changes direction, and releases it once it has not moved for `hold` seconds.
+    fn update(
+        &mut self,
+        movement: f32,
+        threshold: f32,
+        hold: f32,
+        lane: Lane,
+        time: f32,
+        inputs: &mut MessageWriter<LaneInput>,
+    ) {
+        if movement.abs() > threshold {
+            let direction = movement.signum();
+            if direction != self.direction {
+                // 反向算作新的一次按下
+                if self.direction != 0. {
+                    inputs.write(LaneInput {
+                        lane,
+                        pressed: false,
+                        time,
+                    });
+                }
+                inputs.write(LaneInput {
+                    lane,
+                    pressed: true,
+                    time,
+                });
+                self.direction = direction;
+            }
+            self.last_move = time;
+        } else if self.direction != 0. && time - self.last_move > hold {
+            inputs.write(LaneInput {
+                lane,
+                pressed: false,
+                time,
+            });
+            self.direction = 0.;
+        }
+    }
+}
+
+#[derive(Default)]
+struct Turntable {
+    last_value: Option<f32>,
+    scratch: Scratch,
+}
+
+#[derive(Resource, Default)]
+struct Turntables(HashMap<Entity, Turntable>);
+
+#[derive(Resource, Default)]
+struct MouseScratch(Scratch);
+
+fn reset_turntables(mut commands: Commands) {
+    commands.insert_resource(Turntables::default());
+    commands.insert_resource(MouseScratch::default());
+}
+
+/// Keeps the cursor in the window while the mouse is the turntable.
+fn grab_cursor(
+    bindings: Res<KeyBindings>,
+    mut cursor: Single<&mut CursorOptions, With<PrimaryWindow>>,
+) {
+    if bindings.mouse_turntable.enabled {
+        cursor.visible = false;
+        cursor.grab_mode = CursorGrabMode::Locked;
+    }
+}
+
+fn release_cursor(mut cursor: Single<&mut CursorOptions, With<PrimaryWindow>>) {
+    cursor.visible = true;
+    cursor.grab_mode = CursorGrabMode::None;
+}
+
+/// Scratches LS with the first gamepad's turntable axis and RS with the second's.
+fn turntable_input(
+    clock: Res<SongClock>,
+    layout: Res<LaneLayout>,
+    bindings: Res<KeyBindings>,
+    gamepads: Query<(Entity, &Gamepad)>,
+    mut turntables: ResMut<Turntables>,
+    mut inputs: MessageWriter<LaneInput>,
+) {
+    let settings = &bindings.turntable;
+    let elapsed = clock.judge_secs();
+
+    let mut pads: Vec<_> = gamepads.iter().collect();
+    pads.sort_by_key(|(entity, _)| *entity);
+    for ((entity, gamepad), lane) in pads.into_iter().zip([Lane::LS, Lane::RS]) {
+        if layout.column(lane).is_none() {
+            continue;
+        }
+        let Some(value) = gamepad.get(settings.axis) else {
+            continue;
+        };
+
+        let turntable = turntables.0.entry(entity).or_default();
+        let mut movement = value - turntable.last_value.unwrap_or(value);
+        // 转盘轴转过一圈会在 -1 和 1 之间回绕
+        if movement > 1. {
+            movement -= 2.;
+        } else if movement < -1. {
+            movement += 2.;
+        }
+        turntable.last_value = Some(value);
+
+        turntable.scratch.update(
+            movement * settings.sensitivity,
+            settings.deadzone,
+            settings.hold,
+            lane,
+            elapsed,
+            &mut inputs,
+        );
+    }
+}
+
+fn mouse_turntable_input(
+    clock: Res<SongClock>,
+    layout: Res<LaneLayout>,
+    bindings: Res<KeyBindings>,
+    mut motions: MessageReader<MouseMotion>,
+    mut mouse_scratch: ResMut<MouseScratch>,
+    mut inputs: MessageWriter<LaneInput>,
+) {
+    let settings = &bindings.mouse_turntable;
+    if !settings.enabled || layout.column(Lane::LS).is_none() {
+        motions.clear();
+        return;
+    }
+
+    let movement: f32 = motions
+        .read()
+        .map(|motion| match settings.axis {
+            MouseAxis::X => motion.delta.x,
+            MouseAxis::Y => motion.delta.y,
+        })
+        .sum();
+    mouse_scratch.0.update(
+        movement,
+        settings.threshold,
+        settings.hold,
+        Lane::LS,
+        clock.judge_secs(),
+        &mut inputs,
+    );
+}
@@ -0,0 +1,315 @@
+use bevy::prelude::*;
+
+use super::{
+    Screen,
+    bindings::{Binding, BindingInput, KeyBindings},
+    gameplay::{KeyMode, Lane, LaneLayout, PlayStyle},
+};
+
+const COLUMN_HEIGHT: f32 = 360.;
+const COLUMN_Y: f32 = 60.;
+/// Key labels under the columns, blue keys a row above white keys like on a controller.
+const WHITE_LABEL_Y: f32 = -190.;
+const BLUE_LABEL_Y: f32 = -150.;
+
+/// Layouts with their own bindings, in the order Up/Down cycles through them.
+const CONFIG_LAYOUTS: &[(&str, PlayStyle, KeyMode)] = &[
+    ("5KEYS 1P", PlayStyle::Single1P, KeyMode::Beat5K),
+    ("5KEYS 2P", PlayStyle::Single2P, KeyMode::Beat5K),
+    ("7KEYS 1P", PlayStyle::Single1P, KeyMode::Beat7K),
+    ("7KEYS 2P", PlayStyle::Single2P, KeyMode::Beat7K),
+    ("10KEYS", PlayStyle::Double, KeyMode::Beat10K),
+    ("14KEYS", PlayStyle::Double, KeyMode::Beat14K),
+];
+
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(OnEnter(Screen::KeyConfig), spawn_key_config)
+        .add_systems(
+            Update,
+            (
+                keyboard_input,
+                redraw_key_config.run_if(resource_exists_and_changed::<KeyConfig>),
+            )
+                .chain()
+                .run_if(in_state(Screen::KeyConfig)),
+        )
+        .add_systems(OnExit(Screen::KeyConfig), cleanup_key_config_screen);
+}
+
+#[derive(Component)]
+struct OnKeyConfigScreen;
+
+/// Everything below the title, respawned whenever `KeyConfig` changes.
+#[derive(Component)]
+struct KeyConfigView;
+
+#[derive(Resource, Default)]
+struct KeyConfig {
+    /// Index into `CONFIG_LAYOUTS`.
+    layout: usize,
+    /// Index into the layout's columns.
+    cursor: usize,
+    /// Waiting for the key to bind to the cursor lane.
+    listening: bool,
+    message: String,
+}
+
+impl KeyConfig {
+    fn lane_layout(&self) -> LaneLayout {
+        let (_, style, mode) = CONFIG_LAYOUTS[self.layout];
+        LaneLayout::new(style, mode)
+    }
+}
+
+fn spawn_key_config(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(KeyConfig::default());
+
+    commands.spawn((
+        Text2d::new("KEY CONFIG"),
+        TextFont {
+            font: asset_server.load("fonts/KosugiMaru-Regular.ttf"),
+            font_size: 60.0,
+            ..default()
+        },
+        Transform::from_translation(Vec3::new(0., 440., 0.)),
+        OnKeyConfigScreen,
+    ));
+}
+
+fn redraw_key_config(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    config: Res<KeyConfig>,
+    bindings: Res<KeyBindings>,
+    query: Query<Entity, With<KeyConfigView>>,
+) {
+    for entity in &query {
+        commands.entity(entity).despawn();
+    }
+
+    let font = asset_server.load("fonts/KosugiMaru-Regular.ttf");
+    let text_font = |font_size: f32| TextFont {
+        font: font.clone(),
+        font_size,
+        ..default()
+    };
+
+    let (label, _, _) = CONFIG_LAYOUTS[config.layout];
+    commands.spawn((
+        Text2d::new(format!("< {} >", label)),
+        text_font(40.),
+        Transform::from_translation(Vec3::new(0., 340., 0.)),
+        OnKeyConfigScreen,
+        KeyConfigView,
+    ));
+
+    let layout = config.lane_layout();
+    let lane_keys = layout.lane_keys(&bindings);
+    for (i, column) in layout.columns.iter().enumerate() {
+        let color = if i == config.cursor {
+            Color::srgb(1., 0.8, 0.)
+        } else {
+            lane_color(column.lane)
+        };
+        commands.spawn((
+            Mesh2d(meshes.add(Rectangle::new(column.width, COLUMN_HEIGHT))),
+            MeshMaterial2d(materials.add(color)),
+            Transform::from_translation(Vec3::new(column.x, COLUMN_Y, 0.)),
+            OnKeyConfigScreen,
+            KeyConfigView,
+        ));
+        commands.spawn((
+            Text2d::new(format!("{:?}", column.lane)),
+            text_font(20.),
+            Transform::from_translation(Vec3::new(
+                column.x,
+                COLUMN_Y + COLUMN_HEIGHT / 2. + 20.,
+                0.,
+            )),
+            OnKeyConfigScreen,
+            KeyConfigView,
+        ));
+
+        let keys = lane_keys
+            .get(&column.lane)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let (text, text_color) = if keys.is_empty() {
+            ("-".to_string(), Color::srgb(1., 0.2, 0.2))
+        } else {
+            let labels: Vec<_> = keys.iter().map(|key| key.label()).collect();
+            (labels.join("\n"), Color::WHITE)
+        };
+        let label_y = match column.lane.key_number() {
+            Some(n) if n % 2 == 0 => BLUE_LABEL_Y,
+            _ => WHITE_LABEL_Y,
+        };
+        commands.spawn((
+            Text2d::new(text),
+            text_font(20.),
+            TextColor(text_color),
+            Transform::from_translation(Vec3::new(column.x, label_y, 0.)),
+            OnKeyConfigScreen,
+            KeyConfigView,
+        ));
+    }
+
+    let message = if config.listening {
+        let lane = layout.columns[config.cursor].lane;
+        format!("PRESS A KEY OR BUTTON FOR {:?}", lane)
+    } else {
+        config.message.clone()
+    };
+    commands.spawn((
+        Text2d::new(message),
+        text_font(32.),
+        Transform::from_translation(Vec3::new(0., -300., 0.)),
+        OnKeyConfigScreen,
+        KeyConfigView,
+    ));
+
+    let start = bindings
+        .start
+        .first()
+        .copied()
+        .map(Binding::label)
+        .unwrap_or_default();
+    let select = bindings
+        .select
+        .first()
+        .copied()
+        .map(Binding::label)
+        .unwrap_or_default();
+    commands.spawn((
+        Text2d::new(format!(
+            "PRESS A LANE OR {} TO ADD A KEY / DEL CLEAR / UP DOWN LAYOUT / LEFT RIGHT / {} BACK",
+            start, select
+        )),
+        text_font(24.),
+        Transform::from_translation(Vec3::new(0., -440., 0.)),
+        OnKeyConfigScreen,
+        KeyConfigView,
+    ));
+}
+
+/// Dimmed note colors, so the cursor lane stands out.
+fn lane_color(lane: Lane) -> Color {
+    match lane.key_number() {
+        None => Color::srgb(0.4, 0., 0.),
+        Some(n) if n % 2 == 0 => Color::srgb(0., 0., 0.4),
+        Some(_) => Color::srgb(0.3, 0.3, 0.3),
+    }
+}
+
+fn keyboard_input(
+    input: BindingInput,
+    mut config: ResMut<KeyConfig>,
+    mut bindings: ResMut<KeyBindings>,
+    mut next_screen: ResMut<NextState<Screen>>,
+) {
+    let Some(key) = input.get_just_pressed() else {
+        return;
+    };
+    let layout = config.lane_layout();
+
+    if config.listening {
+        config.listening = false;
+        config.message = if bindings.select.contains(&key) {
+            "CANCELLED".to_string()
+        } else {
+            let lane = layout.columns[config.cursor].lane;
+            bind_key(&layout, &mut bindings, lane, key)
+        };
+        return;
+    }
+
+    if bindings.select.contains(&key) {
+        next_screen.set(Screen::Select);
+        return;
+    }
+    if matches!(
+        key,
+        Binding::Key(KeyCode::Delete) | Binding::Key(KeyCode::Backspace)
+    ) {
+        let lane = layout.columns[config.cursor].lane;
+        config.message = clear_lane(&layout, &mut bindings, lane);
+        return;
+    }
+
+    // 按下已绑定的键直接选中该 lane
+    let lane_keys = layout.lane_keys(&bindings);
+    let pressed_lane = layout.columns.iter().position(|column| {
+        lane_keys
+            .get(&column.lane)
+            .is_some_and(|keys| keys.contains(&key))
+    });
+    if let Some(index) = pressed_lane {
+        config.cursor = index;
+        config.listening = true;
+        return;
+    }
+
+    let columns = layout.columns.len();
+    match key {
+        Binding::Key(KeyCode::ArrowLeft) => config.cursor = (config.cursor + columns - 1) % columns,
+        Binding::Key(KeyCode::ArrowRight) => config.cursor = (config.cursor + 1) % columns,
+        Binding::Key(KeyCode::ArrowUp) | Binding::Key(KeyCode::ArrowDown) => {
+            let step = if key == Binding::Key(KeyCode::ArrowUp) {
+                CONFIG_LAYOUTS.len() - 1
+            } else {
+                1
+            };
+            config.layout = (config.layout + step) % CONFIG_LAYOUTS.len();
+            config.cursor = 0;
+        }
+        _ if bindings.start.contains(&key) => config.listening = true,
+        _ => return,
+    }
+    config.message.clear();
+}
+
+/// Adds `key` to the bindings of `lane`, taking it away from any other lane of the layout,
+/// and saves the bindings. Returns the message describing what happened.
+fn bind_key(layout: &LaneLayout, bindings: &mut KeyBindings, lane: Lane, key: Binding) -> String {
+    if bindings.start.contains(&key) {
+        return format!("{} IS ALREADY THE START KEY", key.label());
+    }
+
+    let lane_keys = layout.lane_keys_mut(bindings);
+    if lane_keys.get(&lane).is_some_and(|keys| keys.contains(&key)) {
+        return format!("{} IS ALREADY BOUND TO {:?}", key.label(), lane);
+    }
+    let conflict = lane_keys
+        .iter()
+        .find(|(other, keys)| **other != lane && keys.contains(&key))
+        .map(|(other, _)| *other);
+    if let Some(other) = conflict {
+        lane_keys.get_mut(&other).unwrap().retain(|k| *k != key);
+    }
+    lane_keys.entry(lane).or_default().push(key);
+    bindings.save();
+
+    match conflict {
+        Some(other) => format!("{} MOVED FROM {:?} TO {:?}", key.label(), other, lane),
+        None => format!("{} BOUND TO {:?}", key.label(), lane),
+    }
+}
+
+/// Removes every key and button bound to `lane` and saves the bindings.
+fn clear_lane(layout: &LaneLayout, bindings: &mut KeyBindings, lane: Lane) -> String {
+    layout.lane_keys_mut(bindings).remove(&lane);
+    bindings.save();
+    format!("{:?} CLEARED", lane)
+}
+
+fn cleanup_key_config_screen(
+    mut commands: Commands,
+    query: Query<Entity, With<OnKeyConfigScreen>>,
+) {
+    for entity in &query {
+        commands.entity(entity).despawn();
+    }
+    commands.remove_resource::<KeyConfig>();
+}
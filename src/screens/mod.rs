@@ -1,14 +1,25 @@
+mod bindings;
+mod calibration;
 mod select;
 mod gameplay;
+mod key_config;
+mod result;
 
 use bevy::prelude::*;
 
+use crate::timing_offsets::TimingOffsets;
+
 pub(super) fn plugin(app: &mut App) {
-    app.init_state::<Screen>();
+    app.init_state::<Screen>()
+        .insert_resource(bindings::KeyBindings::load())
+        .insert_resource(TimingOffsets::load());
 
     app.add_plugins((
         select::plugin,
         gameplay::plugin,
+        result::plugin,
+        key_config::plugin,
+        calibration::plugin,
     ));
 }
 
@@ -17,5 +28,8 @@ pub enum Screen {
     #[default]
     Select,
     Gameplay,
+    Result,
+    KeyConfig,
+    Calibration,
     Loading,
 }
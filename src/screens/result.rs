@@ -0,0 +1,243 @@
+use bevy::{prelude::*, sprite::Anchor};
+
+use crate::{
+    resources::{BmsLib, PlayResult},
+    score_store::{ClearLamp, ScoreStore},
+    screens::{
+        Screen,
+        bindings::{Binding, BindingInput, KeyBindings},
+    },
+};
+
+const GRAPH_WIDTH: f32 = 600.;
+const GRAPH_HEIGHT: f32 = 240.;
+const GRAPH_POSITION: Vec2 = Vec2::new(300., 40.);
+/// Most bars drawn in the gauge graph, long charts are sampled down to this.
+const GRAPH_MAX_BARS: usize = 200;
+
+const HISTOGRAM_HEIGHT: f32 = 160.;
+const HISTOGRAM_POSITION: Vec2 = Vec2::new(300., -300.);
+/// Offsets beyond this many milliseconds go into the outermost bins.
+const HISTOGRAM_RANGE_MS: f32 = 100.;
+const HISTOGRAM_BIN_MS: f32 = 4.;
+
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(OnEnter(Screen::Result), (save_score, spawn_result))
+        .add_systems(Update, keyboard_input.run_if(in_state(Screen::Result)))
+        .add_systems(OnExit(Screen::Result), cleanup_result_screen);
+}
+
+#[derive(Component)]
+struct OnResultScreen;
+
+fn save_score(lib: Res<BmsLib>, result: Res<PlayResult>, mut store: ResMut<ScoreStore>) {
+    if result.watched {
+        return;
+    }
+    let entry = lib.cursor_entry().unwrap();
+    store.record(&entry.hash, &result);
+    store.save();
+}
+
+fn spawn_result(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    lib: Res<BmsLib>,
+    result: Res<PlayResult>,
+    bindings: Res<KeyBindings>,
+) {
+    let font = asset_server.load("fonts/KosugiMaru-Regular.ttf");
+    let text_font = TextFont {
+        font: font.clone(),
+        font_size: 40.0,
+        ..default()
+    };
+
+    let entry = lib.cursor_entry().unwrap();
+    commands.spawn((
+        Text2d::new(entry.header.title.clone().unwrap_or_default()),
+        text_font.clone(),
+        Transform::from_translation(Vec3::new(0., 420., 0.)),
+        OnResultScreen,
+    ));
+
+    let status_color = if result.cleared {
+        result.gauge.color()
+    } else {
+        Color::srgb(1., 0.2, 0.2)
+    };
+    commands.spawn((
+        Text2d::new(ClearLamp::new(&result).label()),
+        TextFont {
+            font: font.clone(),
+            font_size: 72.0,
+            ..default()
+        },
+        TextColor(status_color),
+        Transform::from_translation(Vec3::new(0., 320., 0.)),
+        OnResultScreen,
+    ));
+
+    let lines = [
+        format!("JUDGE {}", entry.judge_rank.label()),
+        format!("DJ LEVEL {}", result.dj_level().label()),
+        format!("EX SCORE {} / {}", result.ex_score(), result.max_ex_score()),
+        format!("MAX COMBO {}", result.max_combo),
+        String::new(),
+        format!("PGREAT {}", result.pgreat),
+        format!("GREAT {}", result.great),
+        format!("GOOD {}", result.good),
+        format!("BAD {}", result.bad),
+        format!("POOR {} ({})", result.poor, result.empty_poor),
+        String::new(),
+        format!("FAST {} / SLOW {}", result.fast, result.slow),
+    ];
+    commands.spawn((
+        Text2d::new(lines.join("\n")),
+        text_font.clone(),
+        TextLayout::new_with_justify(Justify::Left),
+        Transform::from_translation(Vec3::new(-700., GRAPH_POSITION.y, 0.)),
+        Anchor::CENTER_LEFT,
+        OnResultScreen,
+    ));
+
+    commands
+        .spawn((
+            Mesh2d(meshes.add(Rectangle::new(GRAPH_WIDTH, GRAPH_HEIGHT))),
+            MeshMaterial2d(materials.add(Color::srgb(0.15, 0.15, 0.15))),
+            Transform::from_translation(GRAPH_POSITION.extend(0.)),
+            OnResultScreen,
+        ))
+        .with_children(|parent| {
+            let history = &result.gauge_history;
+            let bars = history.len().min(GRAPH_MAX_BARS);
+            let bar_width = GRAPH_WIDTH / bars.max(1) as f32;
+            let bar_color = materials.add(result.gauge.color());
+            for i in 0..bars {
+                let value = history[(i + 1) * history.len() / bars - 1];
+                let height = GRAPH_HEIGHT * value / 100.;
+                parent.spawn((
+                    Mesh2d(meshes.add(Rectangle::new(bar_width, height))),
+                    MeshMaterial2d(bar_color.clone()),
+                    Transform::from_translation(Vec3::new(
+                        -GRAPH_WIDTH / 2. + bar_width * (i as f32 + 0.5),
+                        (height - GRAPH_HEIGHT) / 2.,
+                        1.,
+                    )),
+                ));
+            }
+
+            // 合格线
+            let clear_line = result.gauge.clear_line();
+            if clear_line > 0. {
+                parent.spawn((
+                    Mesh2d(meshes.add(Rectangle::new(GRAPH_WIDTH, 2.))),
+                    MeshMaterial2d(materials.add(Color::WHITE)),
+                    Transform::from_translation(Vec3::new(
+                        0.,
+                        GRAPH_HEIGHT * clear_line / 100. - GRAPH_HEIGHT / 2.,
+                        2.,
+                    )),
+                ));
+            }
+        });
+
+    spawn_histogram(&mut commands, &mut meshes, &mut materials, &result.offsets);
+
+    let offset_stats = match result.offset_stats() {
+        Some((mean, deviation)) => format!("MEAN {:+.1}ms / SD {:.1}ms", mean, deviation),
+        None => String::new(),
+    };
+    commands.spawn((
+        Text2d::new(offset_stats),
+        TextFont {
+            font_size: 24.0,
+            ..text_font.clone()
+        },
+        Transform::from_translation(Vec3::new(
+            HISTOGRAM_POSITION.x,
+            HISTOGRAM_POSITION.y + HISTOGRAM_HEIGHT / 2. + 20.,
+            0.,
+        )),
+        OnResultScreen,
+    ));
+
+    let start = bindings.start.first().copied().map(Binding::label);
+    commands.spawn((
+        Text2d::new(format!("PRESS {}", start.unwrap_or_default())),
+        text_font,
+        Transform::from_translation(Vec3::new(0., -460., 0.)),
+        OnResultScreen,
+    ));
+}
+
+/// Bars counting the offsets in each bin, FAST on the left and SLOW on the right.
+fn spawn_histogram(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<ColorMaterial>,
+    offsets: &[f32],
+) {
+    let bins = (HISTOGRAM_RANGE_MS * 2. / HISTOGRAM_BIN_MS) as usize;
+    let mut counts = vec![0u32; bins];
+    for offset in offsets {
+        let bin = ((offset + HISTOGRAM_RANGE_MS) / HISTOGRAM_BIN_MS).floor();
+        counts[(bin.max(0.) as usize).min(bins - 1)] += 1;
+    }
+    let max_count = counts.iter().copied().max().unwrap_or(0).max(1);
+    let bar_width = GRAPH_WIDTH / bins as f32;
+
+    commands
+        .spawn((
+            Mesh2d(meshes.add(Rectangle::new(GRAPH_WIDTH, HISTOGRAM_HEIGHT))),
+            MeshMaterial2d(materials.add(Color::srgb(0.15, 0.15, 0.15))),
+            Transform::from_translation(HISTOGRAM_POSITION.extend(0.)),
+            OnResultScreen,
+        ))
+        .with_children(|parent| {
+            let fast_color = materials.add(Color::srgb(0.3, 0.6, 1.));
+            let slow_color = materials.add(Color::srgb(1., 0.4, 0.3));
+            for (i, count) in counts.iter().enumerate() {
+                let height = HISTOGRAM_HEIGHT * *count as f32 / max_count as f32;
+                let color = if i < bins / 2 {
+                    fast_color.clone()
+                } else {
+                    slow_color.clone()
+                };
+                parent.spawn((
+                    Mesh2d(meshes.add(Rectangle::new(bar_width, height))),
+                    MeshMaterial2d(color),
+                    Transform::from_translation(Vec3::new(
+                        -GRAPH_WIDTH / 2. + bar_width * (i as f32 + 0.5),
+                        (height - HISTOGRAM_HEIGHT) / 2.,
+                        1.,
+                    )),
+                ));
+            }
+
+            // 零点
+            parent.spawn((
+                Mesh2d(meshes.add(Rectangle::new(2., HISTOGRAM_HEIGHT))),
+                MeshMaterial2d(materials.add(Color::WHITE)),
+                Transform::from_translation(Vec3::new(0., 0., 2.)),
+            ));
+        });
+}
+
+fn keyboard_input(
+    input: BindingInput,
+    bindings: Res<KeyBindings>,
+    mut next_screen: ResMut<NextState<Screen>>,
+) {
+    if input.any_just_pressed(&bindings.start) || input.any_just_pressed(&bindings.select) {
+        next_screen.set(Screen::Select);
+    }
+}
+
+fn cleanup_result_screen(mut commands: Commands, query: Query<Entity, With<OnResultScreen>>) {
+    for entity in &query {
+        commands.entity(entity).despawn();
+    }
+}
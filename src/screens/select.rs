@@ -1,136 +1,235 @@
 use std::path::PathBuf;
 
-use bevy::{color::palettes::css::*, prelude::*, sprite::Anchor};
+use bevy::{color::palettes::css::*, platform::collections::HashMap, prelude::*, sprite::Anchor};
 use bms_rs::bms::{BmsOutput, model::Header, parse_bms, prelude::KeyLayoutBeat};
 use encoding_rs::SHIFT_JIS;
 use num_traits::ToPrimitive;
 use walkdir::WalkDir;
 
 use crate::{
-    resources::{BmsEntry, BmsLib},
-    screens::Screen,
+    chart::{JudgeRank, chart_hash, parse_chart},
+    replay::{Replay, ReplayPlayback},
+    resources::{
+        BmsEntry, BmsLib, DemoPlay, DjLevel, GaugeType, PacemakerTarget, PlayOptions, PlaySide,
+    },
+    score_store::{ChartRecord, ClearLamp, ScoreStore},
+    screens::{
+        Screen,
+        bindings::{BindingInput, KeyBindings},
+    },
+    timing_offsets::TimingOffsets,
 };
 
 pub(super) fn plugin(app: &mut App) {
     app.add_systems(OnEnter(Screen::Select), spawn_select)
-        .add_systems(Update, keyboard_input.run_if(in_state(Screen::Select)))
+        .add_systems(
+            Update,
+            (
+                keyboard_input,
+                option_input,
+                replay_input,
+                idle_demo,
+                (update_entry_text, update_rank_text).run_if(resource_changed::<BmsLib>),
+                update_options_text.run_if(
+                    resource_changed::<PlayOptions>
+                        .or(resource_changed::<TimingOffsets>)
+                        .or(any_match_filter::<Added<PlayOptionsText>>),
+                ),
+                update_random_text.run_if(
+                    resource_changed::<BmsLib>
+                        .or(resource_changed::<PlayOptions>)
+                        .or(any_match_filter::<Added<RandomValues>>),
+                ),
+            )
+                .chain()
+                .run_if(in_state(Screen::Select)),
+        )
         .add_systems(OnExit(Screen::Select), cleanup_select_screen)
         .insert_resource(BmsLib {
             cursor: 0,
             bms_arr: vec![],
-        });
+        })
+        .insert_resource(PlayOptions {
+            timing_markers: true,
+            random_seed: rand::random(),
+            ..default()
+        })
+        .insert_resource(ScoreStore::load())
+        .init_resource::<RandomValuesCache>();
 }
 
 #[derive(Component)]
 struct SelectItem;
 
+/// Counts down to the autoplay demo while no key is pressed.
+#[derive(Resource)]
+struct IdleTimer(Timer);
+
 #[derive(Component)]
 struct OnSelectScreen;
 
 #[derive(Component)]
 struct Player;
 
+/// Which header field of the cursor chart the text shows.
 #[derive(Component)]
-struct Genre;
+enum HeaderText {
+    Genre,
+    Title,
+    Artist,
+}
 
 #[derive(Component)]
-struct Title;
+struct BPM;
 
 #[derive(Component)]
-struct Artist;
+struct PlayLevel;
 
 #[derive(Component)]
-struct BPM;
+struct Rank;
 
 #[derive(Component)]
-struct PlayLevel;
+struct PlayOptionsText;
 
 #[derive(Component)]
-struct Rank;
+struct RandomValues;
+
+/// The seed each chart was last evaluated with and the `#RANDOM` values it drew, by cursor.
+/// The values are `None` when the chart could not be read.
+#[derive(Resource, Default)]
+struct RandomValuesCache(HashMap<u32, (u64, Option<Vec<u64>>)>);
 
 const LINE_HEIGHT: f32 = 50.;
 const LINE_WIDTH: f32 = 800.;
 const RIGHT_OFFSET: f32 = 1080. - LINE_WIDTH / 2.;
 const BORDER_THICKNESS: f32 = 2.;
+const LAMP_WIDTH: f32 = 10.;
 
 const BMS_PATH: &str = "./bms";
+const DEMO_IDLE_SECS: f32 = 30.;
 
 fn spawn_select(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
     mut data: ResMut<BmsLib>,
+    store: Res<ScoreStore>,
     asset_server: Res<AssetServer>,
 ) {
-    for entry in WalkDir::new(BMS_PATH) {
-        let entry = match entry {
-            Ok(e) => e,
-            Err(err) => {
+    commands.insert_resource(IdleTimer(Timer::from_seconds(
+        DEMO_IDLE_SECS,
+        TimerMode::Once,
+    )));
+
+    // 从结算画面返回时保留曲目和光标
+    if data.bms_arr.is_empty() {
+        for entry in WalkDir::new(BMS_PATH) {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(err) => {
+                    continue;
+                }
+            };
+            let path = entry.path();
+
+            if !path.is_file() {
                 continue;
             }
-        };
-        let path = entry.path();
-
-        if !path.is_file() {
-            continue;
-        }
 
-        if let Some(ext) = path.extension().and_then(|x| x.to_str()) {
-            if ext.eq_ignore_ascii_case("bms") || ext.eq_ignore_ascii_case("bme") {
-                let bytes = std::fs::read(path).unwrap();
+            if let Some(ext) = path.extension().and_then(|x| x.to_str()) {
+                if ext.eq_ignore_ascii_case("bms") || ext.eq_ignore_ascii_case("bme") {
+                    let bytes = std::fs::read(path).unwrap();
 
-                let (bms_text, _encoding_used, _had_errors) = SHIFT_JIS.decode(&bytes);
-                let bms_text = bms_text.into_owned();
+                    let (bms_text, _encoding_used, _had_errors) = SHIFT_JIS.decode(&bytes);
+                    let bms_text = bms_text.into_owned();
 
-                let BmsOutput { bms, .. }: BmsOutput<KeyLayoutBeat> = parse_bms(&bms_text);
-                data.bms_arr.push(BmsEntry {
-                    header: bms.header,
-                    path: path.to_path_buf(),
-                });
+                    let BmsOutput { bms, .. }: BmsOutput<KeyLayoutBeat> = parse_bms(&bms_text);
+                    data.bms_arr.push(BmsEntry {
+                        judge_rank: JudgeRank::new(&bms, &bms_text),
+                        header: bms.header,
+                        path: path.to_path_buf(),
+                        hash: chart_hash(&bytes),
+                    });
+                }
             }
         }
-    }
 
-    data.bms_arr
-        .sort_by(|a, b| a.header.title.cmp(&b.header.title));
+        data.bms_arr
+            .sort_by(|a, b| a.header.title.cmp(&b.header.title));
+    }
 
     let border_color = materials.add(Color::srgb(1., 1., 1.));
     let selected_border_color = materials.add(Color::srgb(1., 0., 0.));
 
+    let entry = data.cursor_entry().unwrap();
     let text_font = TextFont {
         font: asset_server.load("fonts/KosugiMaru-Regular.ttf"),
         font_size: 50.0,
         ..default()
     };
+    let score_font = TextFont {
+        font_size: 24.0,
+        ..text_font.clone()
+    };
 
     commands.spawn((
-        Text2d::new(data.bms_arr[0].header.genre.clone().unwrap()),
+        Text2d::new(rank_text(&entry.judge_rank)),
+        text_font.clone(),
+        TextLayout::new_with_justify(Justify::Right),
+        Transform::from_translation(Vec2::new(0., 200.).extend(0.)),
+        Anchor::CENTER_RIGHT,
+        OnSelectScreen,
+        Rank,
+    ));
+
+    commands.spawn((
+        Text2d::new(entry.header.genre.clone().unwrap()),
         text_font.clone(),
         TextLayout::new_with_justify(Justify::Right),
         Transform::from_translation(Vec2::new(0., 100.).extend(0.)),
         Anchor::CENTER_RIGHT,
         OnSelectScreen,
-        Genre,
+        HeaderText::Genre,
     ));
 
     commands.spawn((
-        Text2d::new(data.bms_arr[0].header.title.clone().unwrap()),
+        Text2d::new(entry.header.title.clone().unwrap()),
         text_font.clone(),
         TextLayout::new_with_justify(Justify::Right),
         Transform::from_translation(Vec2::new(0., 0.).extend(0.)),
         Anchor::CENTER_RIGHT,
         OnSelectScreen,
-        Title,
+        HeaderText::Title,
     ));
 
     commands.spawn((
-        Text2d::new(data.bms_arr[0].header.artist.clone().unwrap()),
+        Text2d::new(entry.header.artist.clone().unwrap()),
         text_font.clone(),
         TextLayout::new_with_justify(Justify::Right),
         Transform::from_translation(Vec2::new(0., -100.).extend(0.)),
         Anchor::CENTER_RIGHT,
         OnSelectScreen,
-        Artist,
+        HeaderText::Artist,
+    ));
+
+    commands.spawn((
+        Text2d::default(),
+        text_font.clone(),
+        TextLayout::new_with_justify(Justify::Right),
+        Transform::from_translation(Vec2::new(0., -200.).extend(0.)),
+        Anchor::CENTER_RIGHT,
+        OnSelectScreen,
+        PlayOptionsText,
+    ));
+
+    commands.spawn((
+        Text2d::default(),
+        text_font.clone(),
+        TextLayout::new_with_justify(Justify::Right),
+        Transform::from_translation(Vec2::new(0., -300.).extend(0.)),
+        Anchor::CENTER_RIGHT,
+        OnSelectScreen,
+        RandomValues,
     ));
 
     commands
@@ -179,7 +278,9 @@ fn spawn_select(
                 let title = header.header.title.clone().unwrap();
                 let difficulty = header.header.difficulty.clone().unwrap_or(0);
                 let play_level = header.header.play_level.clone().unwrap_or(0);
-                let stack_y = -(i as f32) * (LINE_HEIGHT + BORDER_THICKNESS * 3.);
+                let record = store.get(&header.hash);
+                let stack_y =
+                    (data.cursor as f32 - i as f32) * (LINE_HEIGHT + BORDER_THICKNESS * 3.);
 
                 let play_level_color = match difficulty {
                     0 => GRAY,
@@ -223,6 +324,28 @@ fn spawn_select(
                             Anchor::CENTER_LEFT,
                         ));
 
+                        // 灯
+                        let lamp = record.map_or(ClearLamp::NoPlay, |record| record.lamp);
+                        parent.spawn((
+                            Mesh2d(meshes.add(Rectangle::new(LAMP_WIDTH, LINE_HEIGHT))),
+                            MeshMaterial2d(materials.add(lamp.color())),
+                            Transform::from_translation(
+                                Vec2::new(-BORDER_THICKNESS - LAMP_WIDTH / 2., 0.).extend(0.),
+                            ),
+                        ));
+
+                        if let Some(record) = record {
+                            parent.spawn((
+                                Text2d::new(best_score_text(record)),
+                                score_font.clone(),
+                                TextLayout::new_with_justify(Justify::Right),
+                                Transform::from_translation(
+                                    Vec2::new(LINE_WIDTH - text_offset_x * 4., 0.).extend(1.),
+                                ),
+                                Anchor::CENTER_RIGHT,
+                            ));
+                        }
+
                         // 上
                         parent.spawn((
                             Mesh2d(meshes.add(Rectangle::new(LINE_WIDTH, BORDER_THICKNESS))),
@@ -256,15 +379,91 @@ fn spawn_select(
         });
 }
 
+fn rank_text(judge_rank: &JudgeRank) -> String {
+    format!("JUDGE {}", judge_rank.label())
+}
+
+fn options_text(options: &PlayOptions, offsets: &TimingOffsets) -> String {
+    let side = match options.side {
+        PlaySide::P1 => "1P SIDE",
+        PlaySide::P2 => "2P SIDE",
+    };
+    let markers = if options.timing_markers {
+        "MARKERS ON"
+    } else {
+        "MARKERS OFF"
+    };
+    let offset_ms = if options.offset_ms { " / MS" } else { "" };
+    let autoplay = if options.autoplay { " / AUTOPLAY" } else { "" };
+    format!(
+        "{} / {} GAUGE / PACE {}\n{}{}{}\n{}",
+        side,
+        options.gauge.label(),
+        options.pacemaker.label(),
+        markers,
+        offset_ms,
+        autoplay,
+        offsets.label()
+    )
+}
+
+fn best_score_text(record: &ChartRecord) -> String {
+    let dj_level = DjLevel::new(record.best_ex_score, record.max_ex_score);
+    format!("{} {}", dj_level.label(), record.best_ex_score)
+}
+
+/// Shows the `#RANDOM` values the cursor chart evaluates to, parsing it once per seed.
+fn update_random_text(
+    data: Res<BmsLib>,
+    options: Res<PlayOptions>,
+    mut cache: ResMut<RandomValuesCache>,
+    mut query: Query<&mut Text2d, With<RandomValues>>,
+) {
+    let Some(entry) = data.cursor_entry() else {
+        return;
+    };
+    let seed = options.random_seed;
+    // 每次换曲都会重新抽取种子，所以每首只留最后一次的结果
+    let cached_seed = cache.0.get(&data.cursor).map(|(seed, _)| *seed);
+    if cached_seed != Some(seed) {
+        let values = parse_chart(&entry.path, seed)
+            .inspect_err(|err| warn!("failed to read {}: {}", entry.path.display(), err))
+            .ok()
+            .map(|chart| chart.random_values);
+        cache.0.insert(data.cursor, (seed, values));
+    }
+    let (_, values) = &cache.0[&data.cursor];
+
+    let text = match values {
+        Some(values) => random_text(values, seed),
+        None => "CHART NOT FOUND".to_string(),
+    };
+    for mut text2d in &mut query {
+        text2d.0 = text.clone();
+    }
+}
+
+/// Empty for charts without any `#RANDOM`.
+fn random_text(values: &[u64], seed: u64) -> String {
+    if values.is_empty() {
+        return String::new();
+    }
+
+    let values = values
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!("RANDOM {} (SEED {})", values, seed)
+}
+
 fn keyboard_input(
     keys: Res<ButtonInput<KeyCode>>,
     mut query: Query<&mut Transform, With<SelectItem>>,
     mut data: ResMut<BmsLib>,
+    bindings: Res<KeyBindings>,
+    input: BindingInput,
     mut next_screen: ResMut<NextState<Screen>>,
-    mut query_text: Query<
-        (&mut Text2d, Option<&Genre>, Option<&Title>, Option<&Artist>),
-        With<OnSelectScreen>,
-    >,
 ) {
     if keys.just_pressed(KeyCode::ArrowDown) {
         let offset = LINE_HEIGHT + BORDER_THICKNESS * 3.;
@@ -278,30 +477,6 @@ fn keyboard_input(
         } else {
             data.cursor += 1;
         }
-
-        for (mut text2d, genre, title, artist) in query_text.iter_mut() {
-            if genre.is_some() {
-                text2d.0 = data.bms_arr[data.cursor as usize]
-                    .header
-                    .genre
-                    .clone()
-                    .unwrap();
-            }
-            if title.is_some() {
-                text2d.0 = data.bms_arr[data.cursor as usize]
-                    .header
-                    .title
-                    .clone()
-                    .unwrap();
-            }
-            if artist.is_some() {
-                text2d.0 = data.bms_arr[data.cursor as usize]
-                    .header
-                    .artist
-                    .clone()
-                    .unwrap();
-            }
-        }
     }
 
     if keys.just_pressed(KeyCode::ArrowUp) {
@@ -316,37 +491,148 @@ fn keyboard_input(
         } else {
             data.cursor -= 1;
         }
+    }
 
-        for (mut text2d, genre, title, artist) in query_text.iter_mut() {
-            if genre.is_some() {
-                text2d.0 = data.bms_arr[data.cursor as usize]
-                    .header
-                    .genre
-                    .clone()
-                    .unwrap();
-            }
-            if title.is_some() {
-                text2d.0 = data.bms_arr[data.cursor as usize]
-                    .header
-                    .title
-                    .clone()
-                    .unwrap();
-            }
-            if artist.is_some() {
-                text2d.0 = data.bms_arr[data.cursor as usize]
-                    .header
-                    .artist
-                    .clone()
-                    .unwrap();
-            }
+    if input.any_just_pressed(&bindings.start) {
+        next_screen.set(Screen::Gameplay)
+    }
+
+    if keys.just_pressed(KeyCode::KeyK) {
+        next_screen.set(Screen::KeyConfig)
+    }
+    if keys.just_pressed(KeyCode::KeyC) {
+        next_screen.set(Screen::Calibration)
+    }
+}
+
+fn option_input(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut options: ResMut<PlayOptions>,
+    mut offsets: ResMut<TimingOffsets>,
+) {
+    if keys.just_pressed(KeyCode::Digit1) || keys.just_pressed(KeyCode::Digit2) {
+        options.side = if keys.just_pressed(KeyCode::Digit1) {
+            PlaySide::P1
+        } else {
+            PlaySide::P2
+        };
+    }
+    if keys.just_pressed(KeyCode::KeyG) {
+        let gauges = GaugeType::all();
+        let index = gauges.iter().position(|g| *g == options.gauge).unwrap();
+        options.gauge = gauges[(index + 1) % gauges.len()];
+    }
+    if keys.just_pressed(KeyCode::KeyT) {
+        let targets = PacemakerTarget::all();
+        let index = targets
+            .iter()
+            .position(|t| *t == options.pacemaker)
+            .unwrap();
+        options.pacemaker = targets[(index + 1) % targets.len()];
+    }
+    if keys.just_pressed(KeyCode::KeyM) {
+        options.timing_markers = !options.timing_markers;
+    }
+    if keys.just_pressed(KeyCode::KeyF) {
+        options.offset_ms = !options.offset_ms;
+    }
+    if keys.just_pressed(KeyCode::KeyA) {
+        options.autoplay = !options.autoplay;
+    }
+    // [ ] 调整判定偏移，- = 调整音频偏移
+    let offset_keys = [
+        (KeyCode::BracketLeft, -1, 0),
+        (KeyCode::BracketRight, 1, 0),
+        (KeyCode::Minus, 0, -1),
+        (KeyCode::Equal, 0, 1),
+    ];
+    for (key, judge, audio) in offset_keys {
+        if keys.just_pressed(key) {
+            offsets.judge_ms += judge;
+            offsets.audio_ms += audio;
+            offsets.save();
         }
     }
 
-    if keys.just_pressed(KeyCode::Enter) {
+    // 换曲或按 R 时重新抽取 #RANDOM 种子
+    let reroll = keys.just_pressed(KeyCode::ArrowDown)
+        || keys.just_pressed(KeyCode::ArrowUp)
+        || keys.just_pressed(KeyCode::KeyR);
+    if reroll {
+        options.random_seed = rand::random();
+    }
+}
+
+/// Replays the latest play of the cursor chart on P.
+fn replay_input(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    data: Res<BmsLib>,
+    mut next_screen: ResMut<NextState<Screen>>,
+) {
+    if !keys.just_pressed(KeyCode::KeyP) {
+        return;
+    }
+    let hash = &data.bms_arr[data.cursor as usize].hash;
+    match Replay::load_latest(hash) {
+        Some(replay) => {
+            commands.insert_resource(ReplayPlayback { replay, next: 0 });
+            next_screen.set(Screen::Gameplay)
+        }
+        None => info!("no replay for {}", hash),
+    }
+}
+
+/// Starts the autoplay demo once no key has been pressed for `DEMO_IDLE_SECS`.
+fn idle_demo(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+    mut idle: ResMut<IdleTimer>,
+    mut next_screen: ResMut<NextState<Screen>>,
+) {
+    if keys.get_just_pressed().next().is_some() {
+        idle.0.reset();
+    } else if idle.0.tick(time.delta()).just_finished() {
+        commands.insert_resource(DemoPlay);
         next_screen.set(Screen::Gameplay)
     }
 }
 
+/// Shows the header of the cursor chart.
+fn update_entry_text(data: Res<BmsLib>, mut query: Query<(&mut Text2d, &HeaderText)>) {
+    let Some(entry) = data.cursor_entry() else {
+        return;
+    };
+    for (mut text2d, field) in &mut query {
+        let text = match field {
+            HeaderText::Genre => &entry.header.genre,
+            HeaderText::Title => &entry.header.title,
+            HeaderText::Artist => &entry.header.artist,
+        };
+        text2d.0 = text.clone().unwrap();
+    }
+}
+
+fn update_rank_text(data: Res<BmsLib>, mut query: Query<&mut Text2d, With<Rank>>) {
+    let Some(entry) = data.cursor_entry() else {
+        return;
+    };
+    for mut text2d in &mut query {
+        text2d.0 = rank_text(&entry.judge_rank);
+    }
+}
+
+fn update_options_text(
+    options: Res<PlayOptions>,
+    offsets: Res<TimingOffsets>,
+    mut query: Query<&mut Text2d, With<PlayOptionsText>>,
+) {
+    for mut text2d in &mut query {
+        text2d.0 = options_text(&options, &offsets);
+    }
+}
+
 fn cleanup_select_screen(mut commands: Commands, query: Query<Entity, With<OnSelectScreen>>) {
     for entity in &query {
         commands.entity(entity).despawn();
@@ -0,0 +1,50 @@
+use std::fs;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+const TIMING_OFFSETS_PATH: &str = "./offsets.ron";
+
+/// Global timing offsets in milliseconds, saved to `./offsets.ron`.
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimingOffsets {
+    /// Delays when sounds are played relative to the song time.
+    pub audio_ms: i32,
+    /// Delays where notes are drawn and when presses are judged, so positive values suit
+    /// players who hit late.
+    pub judge_ms: i32,
+}
+
+impl TimingOffsets {
+    pub fn load() -> Self {
+        let Ok(text) = fs::read_to_string(TIMING_OFFSETS_PATH) else {
+            return Self::default();
+        };
+        ron::from_str(&text).unwrap_or_else(|err| {
+            warn!("failed to read {}: {}", TIMING_OFFSETS_PATH, err);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self) {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).unwrap();
+        if let Err(err) = fs::write(TIMING_OFFSETS_PATH, text) {
+            warn!("failed to write {}: {}", TIMING_OFFSETS_PATH, err);
+        }
+    }
+
+    /// `audio_ms` in seconds.
+    pub fn audio(&self) -> f32 {
+        self.audio_ms as f32 / 1000.
+    }
+
+    /// `judge_ms` in seconds.
+    pub fn judge(&self) -> f32 {
+        self.judge_ms as f32 / 1000.
+    }
+
+    /// e.g. `JUDGE +12MS / AUDIO -5MS`.
+    pub fn label(&self) -> String {
+        format!("JUDGE {:+}MS / AUDIO {:+}MS", self.judge_ms, self.audio_ms)
+    }
+}